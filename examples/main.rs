@@ -31,6 +31,8 @@ extern crate relm;
 #[macro_use]
 extern crate relm_derive;
 
+use std::env;
+
 use gdk::RGBA;
 use gtk::{ButtonExt, LabelExt, OrientableExt, WidgetExt};
 use gtk::Orientation::Vertical;
@@ -42,11 +44,18 @@ use mg::{
     DarkTheme,
     DeleteCompletionItem,
     DialogBuilder,
+    HistoryFile,
     Info,
+    KeyPolicy,
     Mg,
     Mode,
     Modes,
     ModeChanged,
+    Motions,
+    Operation,
+    OperatorCommand,
+    Operators,
+    RegistersFile,
     SetMode,
     SetSetting,
     SettingChanged,
@@ -59,6 +68,7 @@ use mg::{
     input,
     question,
 };
+use mg::key::Key;
 use relm::{Relm, Widget};
 use relm_derive::widget;
 
@@ -80,6 +90,7 @@ pub enum Msg {
     Command(AppCommand),
     Echo(Option<String>),
     NewMode(String),
+    Operate(Operation),
     Setting(AppSettingsVariant),
     ShowAlert,
     ShowBlockingInput,
@@ -91,8 +102,10 @@ pub enum Msg {
 }
 
 static MODES: Modes = &[
-    Mode { name: "foo", prefix: "f", show_count: true },
-    Mode { name: "insert", prefix: "i", show_count: false },
+    Mode::new("foo", "f", true),
+    Mode::with_key_policy("insert", "i", false, KeyPolicy::Passthrough(Key::Escape))
+        .color("#2e8b57")
+        .label("INSERT"),
 ];
 
 #[widget]
@@ -148,6 +161,8 @@ impl Widget for Win {
             },
             Echo(answer) => self.model.text = format!("You said: {}", answer.unwrap_or("Nothing".to_string())),
             NewMode(mode) => self.mode_changed(&mode),
+            Operate(operation) => self.model.text = format!("{} {} times on {}", operation.operator,
+                operation.count.unwrap_or(1), operation.motion.unwrap_or_else(|| "the line".to_string())),
             Setting(setting) => self.setting_changed(setting),
             ShowAlert => self.mg.emit(Alert("Blue Alert".to_string())),
             ShowBlockingInput => {
@@ -172,8 +187,12 @@ impl Widget for Win {
         #[name="mg"]
         Mg<AppCommand, AppSettings>(MODES, Ok("examples/main.conf".into()), Some("/home/bouanto".into()), vec![]) {
             DarkTheme: true,
+            HistoryFile: Ok(env::temp_dir().join("mg-example-history")),
+            RegistersFile: Ok(env::temp_dir().join("mg-example-registers")),
             StatusBarVisible: self.model.statusbar_visible,
             Title: self.model.title.clone(),
+            Motions: vec![("w", "word"), ("$", "line-end")],
+            Operators: vec![("d", "delete"), ("y", "yank")],
             Variables: vec![("url", Box::new(|| "http://duckduckgo.com/lite".to_string()))],
             gtk::Box {
                 orientation: Vertical,
@@ -220,10 +239,11 @@ impl Widget for Win {
                 text: "Test",
             },
             AppClose => Command(Quit),
-            CustomCommand(Insert) => mg@SetMode("insert"),
-            CustomCommand(Normal) => mg@SetMode("normal"),
+            CustomCommand(Insert) => mg@SetMode("insert".to_string()),
+            CustomCommand(Normal) => mg@SetMode("normal".to_string()),
             CustomCommand(ref command) => Command(command.clone()),
             ModeChanged(ref mode) => NewMode(mode.clone()),
+            OperatorCommand(ref operation) => Operate(operation.clone()),
             SettingChanged(ref setting) => Setting(setting.clone()),
         }
     }
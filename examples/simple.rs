@@ -114,8 +114,8 @@ impl Widget for Win {
                 text: "Test",
             },
             AppClose => Command(Quit),
-            CustomCommand(Insert) => mg@SetMode("insert"),
-            CustomCommand(Normal) => mg@SetMode("normal"),
+            CustomCommand(Insert) => mg@SetMode("insert".to_string()),
+            CustomCommand(Normal) => mg@SetMode("normal".to_string()),
             CustomCommand(ref command) => Command(command.clone()),
         }
     }
@@ -32,6 +32,7 @@ use completion::{
     DEFAULT_COMPLETER_IDENT,
 };
 use completion::completion_view::Msg::{
+    AddCompleters,
     DeleteCurrentCompletionItem,
     ShowCompletion,
     UpdateCompletions,
@@ -61,9 +62,18 @@ where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
         self.update_completions();
     }
 
+    /// Replace the command completer to complete the current aliases.
+    pub fn update_command_completer(&self) {
+        let mut completers: HashMap<_, Box<dyn completion::Completer>> = HashMap::new();
+        let command_completer = CommandCompleter::<COMM>::with_aliases(&self.model.aliases);
+        completers.insert(DEFAULT_COMPLETER_IDENT, Box::new(command_completer));
+        self.model.completion_view.emit(AddCompleters(completers));
+    }
+
     /// Update the items of the completion view.
     pub fn update_completions(&self) {
         let input = self.model.status_bar_command.clone();
-        self.model.completion_view.emit(UpdateCompletions(self.model.mode_string.clone(), input, self.is_normal_command()));
+        let mode = self.model.engine.current_mode().to_string();
+        self.model.completion_view.emit(UpdateCompletions(mode, input, self.is_normal_command()));
     }
 }
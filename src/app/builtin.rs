@@ -0,0 +1,744 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Commands and settings handled by mg itself, before they reach the application.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand, Value};
+use mg_settings::errors::{Error, Result};
+
+use app::{Mg, Mode, ModesHash, NORMAL_MODE_PREFIX, known_mode};
+use app::color::{ModeStyle, mode_styles, parse_color};
+use app::config::{canonical_path, check_new_mode, include_filename, new_mode, parse_config, parse_file};
+use app::hooks::{HookEvent, parse_hook};
+use app::key_policy::{KeyPolicy, key_policies, parse_key_policy};
+use engine::{Mapping, MappingSource, DEFAULT_MAPPING_TIMEOUT, DEFAULT_WHICH_KEY_TIMEOUT};
+use key::Key;
+use key_parser::parse_keys;
+use self::BuiltinCommand::*;
+
+pub const ALIAS: &str = "alias";
+pub const HELP: &str = "help";
+/// The config command including another config file, handled while parsing the config.
+pub const INCLUDE: &str = "include";
+/// The suffix of the recursive mapping commands (i.e. nmap), also listing the mappings without argument.
+pub const MAP: &str = "map";
+pub const MODE: &str = "mode";
+pub const MODE_COLOR: &str = "mode-color";
+pub const MODE_KEYS: &str = "mode-keys";
+pub const MODE_LABEL: &str = "mode-label";
+/// The suffix of the non-recursive mapping commands (i.e. nnoremap).
+pub const NOREMAP: &str = "noremap";
+pub const ON: &str = "on";
+pub const RELOAD_CONFIG: &str = "reload-config";
+pub const SOURCE: &str = "source";
+pub const UNALIAS: &str = "unalias";
+/// The suffix of the unmapping commands (i.e. nunmap).
+pub const UNMAP: &str = "unmap";
+
+/// The argument of the mapping commands, before the keys, to allow typing the keys at their position on a US
+/// layout (i.e. `nmap <keycode> gg quit`).
+pub const KEYCODE_ARGUMENT: &str = "<keycode>";
+/// The argument of the mode command to show the count in the new mode.
+const SHOW_COUNT: &str = "show-count";
+
+pub const KEYCODE_FALLBACK: &str = "keycode-fallback";
+pub const MAPPING_TIMEOUT: &str = "mapping-timeout";
+pub const PENDING_SHORTCUT_TIMEOUT: &str = "pending-shortcut-timeout";
+pub const WHICH_KEY_TIMEOUT: &str = "which-key-timeout";
+
+/// The commands handled by mg with their help text.
+pub const BUILTIN_COMMANDS: &[(&str, &str)] = &[
+    (ALIAS, "Define a new name for a command"),
+    (HELP, "Show the help of the commands, the settings and the key bindings"),
+    (MAP, "Create a new key binding or list the key bindings"),
+    (MODE, "Add a mode with its name and its prefix"),
+    (MODE_COLOR, "Change the color of the status bar in a mode"),
+    (MODE_KEYS, "Choose which keys typed in a mode reach the focused widget"),
+    (MODE_LABEL, "Change the text shown in the status bar in a mode"),
+    (NOREMAP, "Create a new non-recursive key binding (in the normal mode without mode prefix)"),
+    (ON, "Execute a command when an event happens"),
+    (RELOAD_CONFIG, "Execute the config file again"),
+    ("set", "Change the value of a setting"),
+    (SOURCE, "Execute the commands of a config file"),
+    (UNALIAS, "Delete a command alias"),
+    (UNMAP, "Delete a key binding"),
+];
+
+/// The settings handled by mg with their help text.
+pub const BUILTIN_SETTINGS: &[(&str, &str)] = &[
+    (KEYCODE_FALLBACK, "Allow typing all the key bindings with the keys at their position on a US layout"),
+    (MAPPING_TIMEOUT, "Time in milliseconds to wait for a longer mapping (0 to never wait)"),
+    (PENDING_SHORTCUT_TIMEOUT, "Time in milliseconds before clearing the typed keys (0 to never clear them)"),
+    (WHICH_KEY_TIMEOUT, "Time in milliseconds before listing the mappings continuing the typed keys (0 to disable)"),
+];
+
+/// A command handled by mg instead of the application.
+pub enum BuiltinCommand {
+    /// Define an alias name for a command line.
+    Alias(String, String),
+    /// Add a mode with its name and its prefix, and whether a count can be shown for it.
+    DefineMode {
+        name: String,
+        prefix: String,
+        show_count: bool,
+    },
+    /// Show the help, at the entry of the topic if any.
+    Help(Option<String>),
+    /// Show the mappings of all the modes or of the mode with this name or prefix.
+    ListMappings(Option<String>),
+    /// Create a mapping in a mode added at runtime.
+    /// The mappings of the other modes are parsed by the settings parser.
+    Map {
+        action: String,
+        keycode_fallback: bool,
+        keys: Vec<Key>,
+        mode: String,
+    },
+    /// Set the background color of the status bar in a mode, or remove it if there is no color.
+    ModeColor(String, Option<String>),
+    /// Set what happens to the keys typed in a mode added by the user.
+    ModeKeys(String, KeyPolicy),
+    /// Set the text shown in the status bar in a mode, or remove it if there is no label.
+    ModeLabel(String, Option<String>),
+    /// Create a mapping whose action is a key sequence which is not remapped.
+    Noremap {
+        action: String,
+        keycode_fallback: bool,
+        keys: Vec<Key>,
+        mode: String,
+    },
+    /// Execute a command each time the event happens.
+    On(HookEvent, String),
+    /// Forget the mappings and execute the config file again.
+    ReloadConfig,
+    /// Execute the commands of another config file.
+    Source(PathBuf),
+    /// Remove an alias.
+    Unalias(String),
+    /// Remove a mapping from a mode added at runtime.
+    Unmap {
+        keys: Vec<Key>,
+        mode: String,
+    },
+}
+
+/// Get the default values of the settings handled by mg.
+pub fn builtin_setting_defaults() -> HashMap<String, Value> {
+    let mut defaults = HashMap::new();
+    defaults.insert(KEYCODE_FALLBACK.to_string(), Value::Bool(false));
+    defaults.insert(MAPPING_TIMEOUT.to_string(), Value::Int(i64::from(DEFAULT_MAPPING_TIMEOUT)));
+    defaults.insert(PENDING_SHORTCUT_TIMEOUT.to_string(), Value::Int(0));
+    defaults.insert(WHICH_KEY_TIMEOUT.to_string(), Value::Int(i64::from(DEFAULT_WHICH_KEY_TIMEOUT)));
+    defaults
+}
+
+/// Replace the alias at the start of the command line by its command, as long as this command starts with an alias.
+/// An alias whose command starts with its own name (i.e. `alias open open -n`) is expanded only once, and aliases
+/// expanding into each other are reported as an error.
+pub fn expand_aliases(aliases: &HashMap<String, String>, line: &str) -> Result<String> {
+    let mut line = line.to_string();
+    let mut expanded_aliases = HashSet::new();
+    let mut last_alias = String::new();
+    loop {
+        let expanded_line = {
+            let (name, args) = split_command(&line);
+            let command =
+                match aliases.get(name) {
+                    Some(command) if name != last_alias => command,
+                    _ => return Ok(line.clone()),
+                };
+            if !expanded_aliases.insert(name.to_string()) {
+                return Err(Error::Msg(format!("Recursive alias: {}", name)));
+            }
+            last_alias = name.to_string();
+            expand_arguments(command, args)
+        };
+        line = expanded_line;
+    }
+}
+
+/// Expand the positional arguments (`$1` to `$9` and `$*`) in the command of an alias.
+/// The arguments are appended to the command if it does not contain any of them.
+fn expand_arguments(command: &str, args: &str) -> String {
+    let positional_args: Vec<_> = args.split_whitespace().collect();
+    let mut result = String::new();
+    let mut has_placeholder = false;
+    let mut chars = command.chars().peekable();
+    while let Some(character) = chars.next() {
+        if character == '$' {
+            match chars.peek().cloned() {
+                Some('*') => {
+                    chars.next();
+                    has_placeholder = true;
+                    result.push_str(args);
+                },
+                Some('$') => {
+                    chars.next();
+                    result.push('$');
+                },
+                Some(digit @ '1' ..= '9') => {
+                    chars.next();
+                    has_placeholder = true;
+                    let index = digit.to_digit(10).unwrap_or_default() as usize - 1;
+                    result.push_str(positional_args.get(index).cloned().unwrap_or_default());
+                },
+                _ => result.push(character),
+            }
+        }
+        else {
+            result.push(character);
+        }
+    }
+    if !has_placeholder && !args.is_empty() {
+        result.push(' ');
+        result.push_str(args);
+    }
+    result
+}
+
+/// Parse the arguments of a mapping command: the keys and the action.
+/// Returns the keys, the action and whether the keys can be typed at their position on a US layout.
+fn parse_mapping_args<'a>(command: &str, args: &'a str) -> Result<(Vec<Key>, &'a str, bool)> {
+    let (first_arg, rest) = split_command(args);
+    let keycode_fallback = first_arg == KEYCODE_ARGUMENT;
+    let (keys, action) =
+        if keycode_fallback {
+            split_command(rest)
+        }
+        else {
+            (first_arg, rest)
+        };
+    if keys.is_empty() || action.trim().is_empty() {
+        Err(Error::Msg(format!("Argument required for {}", command)))
+    }
+    else {
+        parse_keys(keys).map(|keys| (keys, action, keycode_fallback))
+    }
+}
+
+/// Parse a non-recursive mapping command in the mode with this prefix.
+/// The action is always a key sequence, so it is checked here.
+fn parse_noremap(command: &str, mode: &str, args: &str) -> Result<BuiltinCommand> {
+    let (keys, action, keycode_fallback) = parse_mapping_args(command, args)?;
+    parse_keys(action)?;
+    Ok(Noremap {
+        action: action.to_string(),
+        keycode_fallback,
+        keys,
+        mode: mode.to_string(),
+    })
+}
+
+/// Parse a mapping or unmapping command of a mode unknown to the settings parser (i.e. a mode added with the
+/// mode command).
+/// Returns None if the line is not such a command.
+pub fn parse_mode_mapping(line: &str) -> Option<Result<BuiltinCommand>> {
+    let (command, args) = split_command(line);
+    let result =
+        if command.len() > UNMAP.len() && command.ends_with(UNMAP) {
+            let mode = command[..command.len() - UNMAP.len()].to_string();
+            let keys = args.trim_end();
+            if keys.is_empty() {
+                Err(Error::Msg(format!("Argument required for {}", command)))
+            }
+            else {
+                parse_keys(keys).map(|keys| Unmap {
+                    keys,
+                    mode,
+                })
+            }
+        }
+        else if command.len() > MAP.len() && command.ends_with(MAP) {
+            let mode = command[..command.len() - MAP.len()].to_string();
+            parse_mapping_args(command, args).map(|(keys, action, keycode_fallback)| Map {
+                action: action.to_string(),
+                keycode_fallback,
+                keys,
+                mode,
+            })
+        }
+        else {
+            return None;
+        };
+    Some(result)
+}
+
+/// Get the mode prefix of a command made of a mode prefix and a suffix (i.e. `n` for `nnoremap`).
+/// Returns None if the command does not start with the prefix of a mode.
+fn mode_prefix<'a>(command: &'a str, suffix: &str, modes: &ModesHash) -> Option<&'a str> {
+    if command.ends_with(suffix) {
+        let prefix = &command[..command.len() - suffix.len()];
+        if modes.contains_key(prefix) {
+            return Some(prefix);
+        }
+    }
+    None
+}
+
+/// Parse a built-in command.
+/// The modes are needed to recognize the commands starting with a mode prefix.
+/// Returns None if the line is not a built-in command.
+pub fn parse_builtin(line: &str, modes: &ModesHash) -> Option<Result<BuiltinCommand>> {
+    let (command, args) = split_command(line);
+    let result =
+        match command {
+            // Without prefix, the non-recursive mappings are created in the normal mode.
+            NOREMAP => parse_noremap(command, NORMAL_MODE_PREFIX, args),
+            _ if mode_prefix(command, NOREMAP, modes).is_some() =>
+                parse_noremap(command, &command[..command.len() - NOREMAP.len()], args),
+            // A mapping command without argument (i.e. nmap) lists the mappings of its mode.
+            _ if mode_prefix(command, MAP, modes).is_some() && !command.ends_with(UNMAP) && args.trim().is_empty() =>
+                Ok(ListMappings(Some(command[..command.len() - MAP.len()].to_string()))),
+            ALIAS => {
+                let (name, command) = split_command(args);
+                if name.is_empty() || command.is_empty() {
+                    Err(Error::Msg(format!("Argument required for {}", ALIAS)))
+                }
+                else {
+                    Ok(Alias(name.to_string(), command.trim_end().to_string()))
+                }
+            },
+            HELP => {
+                let topic = args.trim_end();
+                if topic.is_empty() {
+                    Ok(Help(None))
+                }
+                else {
+                    Ok(Help(Some(topic.to_string())))
+                }
+            },
+            MAP => {
+                let mode = args.trim_end();
+                if mode.is_empty() {
+                    Ok(ListMappings(None))
+                }
+                else if mode.contains(char::is_whitespace) {
+                    Err(Error::Msg(format!("Usage: {} [mode]", MAP)))
+                }
+                else {
+                    Ok(ListMappings(Some(mode.to_string())))
+                }
+            },
+            ON => parse_hook(args).map(|(event, command)| On(event, command)),
+            MODE => {
+                let mut args = args.split_whitespace();
+                match (args.next(), args.next(), args.next(), args.next()) {
+                    (Some(name), Some(prefix), show_count, None) if show_count.map_or(true, |arg| arg == SHOW_COUNT) =>
+                        Ok(DefineMode {
+                            name: name.to_string(),
+                            prefix: prefix.to_string(),
+                            show_count: show_count.is_some(),
+                        }),
+                    _ => Err(Error::Msg(format!("Usage: {} <name> <prefix> [{}]", MODE, SHOW_COUNT))),
+                }
+            },
+            MODE_COLOR | MODE_LABEL => {
+                let (mode, value) = split_command(args);
+                let value = value.trim_end();
+                let value =
+                    if value.is_empty() {
+                        None
+                    }
+                    else {
+                        Some(value.to_string())
+                    };
+                if mode.is_empty() {
+                    Err(Error::Msg(format!("Argument required for {}", command)))
+                }
+                else if command == MODE_COLOR {
+                    Ok(ModeColor(mode.to_string(), value))
+                }
+                else {
+                    Ok(ModeLabel(mode.to_string(), value))
+                }
+            },
+            MODE_KEYS => {
+                let (mode, policy) = split_command(args);
+                if mode.is_empty() {
+                    Err(Error::Msg(format!("Argument required for {}", MODE_KEYS)))
+                }
+                else {
+                    parse_key_policy(policy).map(|policy| ModeKeys(mode.to_string(), policy))
+                }
+            },
+            RELOAD_CONFIG => no_argument(RELOAD_CONFIG, args).map(|_| ReloadConfig),
+            SOURCE => {
+                if args.is_empty() {
+                    Err(Error::Msg(format!("Argument required for {}", SOURCE)))
+                }
+                else {
+                    Ok(Source(PathBuf::from(args.trim_end())))
+                }
+            },
+            UNALIAS => {
+                if args.is_empty() {
+                    Err(Error::Msg(format!("Argument required for {}", UNALIAS)))
+                }
+                else {
+                    Ok(Unalias(args.trim_end().to_string()))
+                }
+            },
+            _ => return None,
+        };
+    Some(result)
+}
+
+/// Split a command line into the command name and its arguments.
+/// The trailing spaces of the arguments are kept since they can be significant (i.e. in `:open `).
+pub fn split_command(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim_start()),
+        None => (line.trim_end(), ""),
+    }
+}
+
+/// Convert a setting value to a boolean.
+fn to_bool(name: &str, value: &Value) -> Result<bool> {
+    match *value {
+        Value::Bool(value) => Ok(value),
+        _ => Err(Error::Msg(format!("{} must be a boolean", name))),
+    }
+}
+
+/// Convert a setting value to a duration in milliseconds.
+fn to_duration(name: &str, value: &Value) -> Result<u32> {
+    match *value {
+        Value::Int(duration) if duration >= 0 && duration <= i64::from(u32::max_value()) => Ok(duration as u32),
+        _ => Err(Error::Msg(format!("{} must be a number of milliseconds between 0 and {}", name, u32::max_value()))),
+    }
+}
+
+/// Check that a command was called without argument.
+fn no_argument(command: &str, args: &str) -> Result<()> {
+    if args.trim_end().is_empty() {
+        Ok(())
+    }
+    else {
+        Err(Error::Msg(format!("{} does not take any argument", command)))
+    }
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Execute a built-in command.
+    pub fn builtin_command(&mut self, command: BuiltinCommand) {
+        match command {
+            Alias(name, command) => {
+                self.model.aliases.insert(name, command);
+                self.update_command_completer();
+                self.update_command_names();
+            },
+            DefineMode { name, prefix, show_count } => {
+                self.add_mode(name, prefix, show_count);
+            },
+            Help(topic) => self.help(topic),
+            ListMappings(mode) => self.list_mappings(mode),
+            Map { action, keycode_fallback, keys, mode } => {
+                if let Some(mode_name) = self.mode_name(&mode) {
+                    let mapping = Mapping::new(action, keycode_fallback, true, self.model.command_source);
+                    self.model.engine.add_mapping(&mode_name, keys, mapping);
+                }
+            },
+            ModeColor(mode, color) => {
+                let color =
+                    match color.map(|color| parse_color(&color)) {
+                        Some(Ok(color)) => Some(color),
+                        Some(Err(error)) => {
+                            self.error(error);
+                            return;
+                        },
+                        None => None,
+                    };
+                if self.check_mode_name(&mode) {
+                    self.model.mode_styles.entry(mode).or_insert_with(ModeStyle::default).color = color;
+                    self.show_mode_style();
+                }
+            },
+            ModeKeys(mode, policy) => {
+                if known_mode(&mode) != Mode::Unknown {
+                    self.error(Error::Msg(format!("Cannot change the keys of the {} mode", mode)));
+                }
+                else if self.check_mode_name(&mode) {
+                    self.model.key_policies.insert(mode, policy);
+                    self.update_key_handling();
+                }
+            },
+            ModeLabel(mode, label) => {
+                if self.check_mode_name(&mode) {
+                    self.model.mode_styles.entry(mode).or_insert_with(ModeStyle::default).label = label;
+                    self.show_mode_style();
+                }
+            },
+            Noremap { action, keycode_fallback, keys, mode } => {
+                if let Some(mode_name) = self.mode_name(&mode) {
+                    let mapping = Mapping::new(action, keycode_fallback, false, self.model.command_source);
+                    self.model.engine.add_mapping(&mode_name, keys, mapping);
+                }
+            },
+            On(event, command) => self.add_hook(event, command),
+            ReloadConfig => self.reload_config(),
+            Source(filename) => self.source(filename),
+            Unalias(name) => {
+                if self.model.aliases.remove(&name).is_none() {
+                    self.error(Error::Msg(format!("No such alias: {}", name)));
+                }
+                self.update_command_completer();
+                self.update_command_names();
+            },
+            Unmap { keys, mode } => {
+                if let Some(mode_name) = self.mode_name(&mode) {
+                    self.model.engine.remove_mapping(&mode_name, &keys);
+                }
+            },
+        }
+    }
+
+    /// Add a mode whose mappings are usable right away.
+    /// Returns false if the mode cannot be added.
+    pub fn add_mode(&mut self, name: String, prefix: String, show_count: bool) -> bool {
+        if let Err(error) = check_new_mode(&self.model.modes, &name, &prefix) {
+            self.error(error);
+            return false;
+        }
+        let mode = new_mode(name, prefix, show_count);
+        self.model.key_policies.insert(mode.name.clone(), KeyPolicy::PassUnmapped);
+        self.model.mode_styles.insert(mode.name.clone(), ModeStyle::default());
+        self.model.modes.insert(mode.prefix.clone(), mode);
+        self.update_command_names();
+        true
+    }
+
+    /// Get the name of the mode with this prefix or show an error if there is none.
+    fn mode_name(&mut self, prefix: &str) -> Option<String> {
+        let name = self.model.modes.get(prefix).map(|mode| mode.name.clone());
+        if name.is_none() {
+            self.error(Error::Msg(format!("Unknown mode prefix: {}", prefix)));
+        }
+        name
+    }
+
+    /// Check that the name is the name of a mode and show an error otherwise.
+    pub fn check_mode_name(&mut self, name: &str) -> bool {
+        let is_mode = known_mode(name) != Mode::Unknown || self.model.modes.values().any(|mode| mode.name == name);
+        if !is_mode {
+            self.error(Error::Msg(format!("Unknown mode: {}", name)));
+        }
+        is_mode
+    }
+
+    /// Change a setting handled by mg.
+    /// Returns None if the setting is not a built-in setting, and an error if the value is invalid.
+    pub fn set_builtin_setting(&mut self, name: &str, value: &Value) -> Option<Result<()>> {
+        let result =
+            match name {
+                KEYCODE_FALLBACK =>
+                    to_bool(name, value).map(|enabled| {
+                        self.model.engine.set_keycode_fallback(enabled);
+                        self.update_key_handling();
+                    }),
+                MAPPING_TIMEOUT =>
+                    to_duration(name, value).map(|timeout| self.model.engine.set_mapping_timeout(timeout)),
+                PENDING_SHORTCUT_TIMEOUT =>
+                    to_duration(name, value).map(|timeout| self.model.engine.set_pending_shortcut_timeout(timeout)),
+                WHICH_KEY_TIMEOUT =>
+                    to_duration(name, value).map(|timeout| self.model.engine.set_which_key_timeout(timeout)),
+                _ => return None,
+            };
+        Some(result)
+    }
+
+    /// Forget the user mappings, aliases and hooks and execute the config file (with its included files) again.
+    /// The modes added by the application are kept with their mappings, key policy and style.
+    fn reload_config(&mut self) {
+        if self.model.reloading_config {
+            self.error(Error::Msg(format!("Cannot use {} while reloading the config", RELOAD_CONFIG)));
+            return;
+        }
+        if let Some(filename) = self.model.settings_filename.clone() {
+            self.model.reloading_config = true;
+            self.model.aliases.clear();
+            self.model.hooks.clear();
+            self.update_command_completer();
+            let (parser, parse_result, mut modes) =
+                parse_config(filename, self.model.user_modes, self.model.include_path.clone());
+            let mut key_policies = key_policies(&modes);
+            let mut mode_styles = mode_styles(&modes);
+            let mut old_mappings = self.model.engine.replace_mappings(HashMap::new());
+            let mut mappings = HashMap::new();
+            for prefix in &self.model.app_modes {
+                if let Some(mode) = self.model.modes.remove(prefix) {
+                    if let Some(policy) = self.model.key_policies.remove(&mode.name) {
+                        key_policies.insert(mode.name.clone(), policy);
+                    }
+                    if let Some(style) = self.model.mode_styles.remove(&mode.name) {
+                        mode_styles.insert(mode.name.clone(), style);
+                    }
+                    if let Some(mode_mappings) = old_mappings.remove(&mode.name) {
+                        mappings.insert(mode.name.clone(), mode_mappings);
+                    }
+                    modes.insert(prefix.clone(), mode);
+                }
+            }
+            self.model.settings_parser = Box::new(parser);
+            self.model.key_policies = key_policies;
+            self.model.engine.replace_mappings(mappings);
+            self.model.mode_styles = mode_styles;
+            self.model.modes = modes;
+            self.update_command_names();
+            self.update_key_handling();
+            self.show_mode_style();
+            self.model.command_source = MappingSource::Config;
+            self.execute_commands(parse_result, false, None);
+            self.model.command_source = MappingSource::Runtime;
+            self.model.reloading_config = false;
+            // Stop listing the forgotten mappings and aliases, even when the config does not change the completers.
+            self.update_mapping_completer();
+            self.update_help_completer();
+        }
+        else {
+            self.error(Error::Msg("No config file to reload".to_string()));
+        }
+    }
+
+    /// Execute the commands of a config file.
+    /// A file which is already being sourced is not sourced again, to avoid an infinite recursion.
+    fn source(&mut self, filename: PathBuf) {
+        let filename = include_filename(self.model.include_path.as_ref(), &filename);
+        let canonical_file = canonical_path(&filename);
+        if self.model.sourced_files.contains(&canonical_file) {
+            self.error(Error::Msg(format!("Recursive source of {}", filename.display())));
+            return;
+        }
+        self.model.sourced_files.push(canonical_file);
+        let mut modes = self.model.modes.clone();
+        let mut aliases = self.model.aliases.clone();
+        let parse_result = parse_file(&mut *self.model.settings_parser, &mut modes, &mut aliases, &filename,
+            self.model.include_path.as_ref(), &mut self.model.sourced_files);
+        let previous_source = self.model.command_source;
+        self.model.command_source = MappingSource::Sourced;
+        self.execute_commands(parse_result, false, None);
+        self.model.command_source = previous_source;
+        self.model.sourced_files.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use app::config::new_mode;
+    use key::Key::Char;
+    use super::BuiltinCommand::{ListMappings, Map, Noremap, Unmap};
+    use super::{expand_aliases, expand_arguments, parse_builtin, parse_mode_mapping};
+
+    #[test]
+    fn alias_arguments() {
+        assert_eq!(expand_arguments("open", ""), "open");
+        assert_eq!(expand_arguments("open", "foo bar"), "open foo bar");
+        assert_eq!(expand_arguments("open $2 $1", "foo bar"), "open bar foo");
+        assert_eq!(expand_arguments("open $3", "foo bar"), "open ");
+        assert_eq!(expand_arguments("echo $* $$1", "foo bar"), "echo foo bar $1");
+        // The arguments are appended when there is no placeholder, even if there is a `$`.
+        assert_eq!(expand_arguments("echo $HOME", "foo bar"), "echo $HOME foo bar");
+        assert_eq!(expand_arguments("echo $$1 $", "foo"), "echo $1 $ foo");
+        assert_eq!(expand_arguments("echo $HOME", ""), "echo $HOME");
+    }
+
+    #[test]
+    fn recursive_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("o".to_string(), "open".to_string());
+        aliases.insert("open".to_string(), "open -n".to_string());
+        aliases.insert("first".to_string(), "second".to_string());
+        aliases.insert("second".to_string(), "first".to_string());
+        assert_eq!(expand_aliases(&aliases, "o foo").unwrap(), "open -n foo");
+        assert_eq!(expand_aliases(&aliases, "quit").unwrap(), "quit");
+        assert!(expand_aliases(&aliases, "first").is_err());
+    }
+
+    #[test]
+    fn parse_list_mappings() {
+        let mut modes = HashMap::new();
+        modes.insert("n".to_string(), new_mode("normal".to_string(), "n".to_string(), true));
+        match parse_builtin("nmap", &modes) {
+            Some(Ok(ListMappings(Some(mode)))) => assert_eq!(mode, "n"),
+            _ => panic!("Expecting a mapping list"),
+        }
+        match parse_builtin("map", &modes) {
+            Some(Ok(ListMappings(None))) => (),
+            _ => panic!("Expecting a mapping list"),
+        }
+        assert!(parse_builtin("nmap gg quit", &modes).is_none());
+        assert!(parse_builtin("sitemap", &modes).is_none());
+        assert!(parse_builtin("heatmap", &modes).is_none());
+    }
+
+    #[test]
+    fn parse_noremap_commands() {
+        let mut modes = HashMap::new();
+        modes.insert("c".to_string(), new_mode("command".to_string(), "c".to_string(), false));
+        match parse_builtin("noremap j gg", &modes) {
+            Some(Ok(Noremap { action, keys, mode, .. })) => {
+                assert_eq!(action, "gg");
+                assert_eq!(keys, vec![Char('j')]);
+                assert_eq!(mode, "n");
+            },
+            _ => panic!("Expecting a non-recursive mapping"),
+        }
+        match parse_builtin("cnoremap <C-a> <Home>", &modes) {
+            Some(Ok(Noremap { mode, .. })) => assert_eq!(mode, "c"),
+            _ => panic!("Expecting a non-recursive mapping"),
+        }
+        assert!(parse_builtin("noremap j <Foo>", &modes).map_or(false, |result| result.is_err()));
+        assert!(parse_builtin("noremap <Foo> gg", &modes).map_or(false, |result| result.is_err()));
+        assert!(parse_builtin("xnoremap j gg", &modes).is_none());
+    }
+
+    #[test]
+    fn parse_mapping_of_added_mode() {
+        match parse_mode_mapping("fmap <keycode> gg quit") {
+            Some(Ok(Map { action, keycode_fallback, keys, mode })) => {
+                assert_eq!(action, "quit");
+                assert!(keycode_fallback);
+                assert_eq!(keys, vec![Char('g'), Char('g')]);
+                assert_eq!(mode, "f");
+            },
+            _ => panic!("Expecting a mapping"),
+        }
+        match parse_mode_mapping("funmap gg") {
+            Some(Ok(Unmap { keys, mode })) => {
+                assert_eq!(keys, vec![Char('g'), Char('g')]);
+                assert_eq!(mode, "f");
+            },
+            _ => panic!("Expecting an unmapping"),
+        }
+        assert!(parse_mode_mapping("fmap gg").map_or(false, |result| result.is_err()));
+        assert!(parse_mode_mapping("funmap").map_or(false, |result| result.is_err()));
+        assert!(parse_mode_mapping("map").is_none());
+        assert!(parse_mode_mapping("quit").is_none());
+    }
+}
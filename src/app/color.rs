@@ -19,6 +19,8 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
+use std::collections::HashMap;
+
 use glib::object::{IsA, Object, ObjectExt};
 use gdk::RGBA;
 use gtk::{
@@ -29,11 +31,45 @@ use gtk::{
     WidgetExt,
 };
 use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+use mg_settings::errors::{Error, Result};
 
-use app::Mg;
+use app::{Mg, Mode, ModesHash, known_mode};
 
 const TRANSPARENT: &RGBA = &RGBA { red: 0.0, green: 0.0, blue: 0.0, alpha: 0.0 };
 
+/// How the status bar looks in a mode.
+#[derive(Clone, Default)]
+pub struct ModeStyle {
+    pub color: Option<RGBA>,
+    pub css_class: Option<String>,
+    pub label: Option<String>,
+}
+
+/// Get the styles of the modes, as specified in their definition.
+pub fn mode_styles(modes: &ModesHash) -> HashMap<String, ModeStyle> {
+    modes.values()
+        .map(|mode| {
+            let color = mode.color.as_ref().and_then(|color| {
+                parse_color(color)
+                    .map_err(|error| warn!("{}", error))
+                    .ok()
+            });
+            let style = ModeStyle {
+                color,
+                css_class: mode.css_class.clone(),
+                label: mode.label.clone(),
+            };
+            (mode.name.clone(), style)
+        })
+        .collect()
+}
+
+/// Parse a color name (i.e. "green") or a CSS color (i.e. "#2e8b57").
+pub fn parse_color(color: &str) -> Result<RGBA> {
+    color.parse()
+        .map_err(|_| Error::Msg(format!("Invalid color: {}", color)))
+}
+
 impl<COMM, SETT> Mg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
           SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
@@ -44,12 +80,45 @@ impl<COMM, SETT> Mg<COMM, SETT>
         style_context.get_color(StateFlags::NORMAL)
     }
 
-    /// Reset the background and foreground colors of the status bar.
+    /// Get the text shown in the status bar for the current mode.
+    /// The modes of mg do not show their name when they have no label.
+    pub fn mode_label(&self) -> String {
+        let mode = self.model.engine.current_mode();
+        match self.model.mode_styles.get(mode).and_then(|style| style.label.clone()) {
+            Some(label) => label,
+            None if known_mode(mode) == Mode::Unknown => mode.to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Reset the background and foreground colors of the status bar to the colors of the current mode.
     pub fn reset_colors(&self) {
         let status_bar = self.status_bar.widget();
         // TODO: switch to CSS.
-        status_bar.override_background_color(StateFlags::NORMAL, Some(TRANSPARENT));
-        status_bar.override_color(StateFlags::NORMAL, Some(&self.model.foreground_color));
+        match self.model.mode_styles.get(self.model.engine.current_mode()).and_then(|style| style.color) {
+            Some(color) => {
+                status_bar.override_background_color(StateFlags::NORMAL, Some(&color));
+                white_foreground(status_bar);
+            },
+            None => {
+                status_bar.override_background_color(StateFlags::NORMAL, Some(TRANSPARENT));
+                status_bar.override_color(StateFlags::NORMAL, Some(&self.model.foreground_color));
+            },
+        }
+    }
+
+    /// Set the CSS class of the status bar to the class of the current mode.
+    pub fn set_mode_css_class(&mut self) {
+        let style_context = self.status_bar.widget().get_style_context();
+        if let Some(css_class) = self.model.mode_css_class.take() {
+            style_context.remove_class(&css_class);
+        }
+        let css_class = self.model.mode_styles.get(self.model.engine.current_mode())
+            .and_then(|style| style.css_class.clone());
+        if let Some(ref css_class) = css_class {
+            style_context.add_class(css_class);
+        }
+        self.model.mode_css_class = css_class;
     }
 
     /// Use the dark variant of the theme if available.
@@ -19,14 +19,11 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::collections::HashMap;
-
 use mg_settings::{
     self,
     Command,
     EnumFromStr,
     EnumMetaData,
-    ParseResult,
     SettingCompletion,
     SpecialCommand,
 };
@@ -45,19 +42,35 @@ use app::{
     ENTRY_DELETE_NEXT_WORD,
     ENTRY_DELETE_PREVIOUS_WORD,
     ENTRY_END,
+    ENTRY_HISTORY_NEXT,
+    ENTRY_HISTORY_PREVIOUS,
+    ENTRY_HISTORY_SEARCH,
     ENTRY_NEXT_CHAR,
     ENTRY_NEXT_WORD,
     ENTRY_PREVIOUS_CHAR,
     ENTRY_PREVIOUS_WORD,
     ENTRY_SMART_HOME,
+    HELP_PAGE_DOWN,
+    HELP_PAGE_UP,
     PASTE,
+    PASTE_HISTORY,
     PASTE_SELECTION,
+    RECORD_MACRO,
+    REPEAT_LAST,
+    REPLAY_MACRO,
+    SELECT_REGISTER,
 };
 use app::ActivationType::{self, Final};
+use app::builtin::{BuiltinCommand, expand_aliases, split_command};
+use app::config::{ConfigCommand, ConfigResult, parse_line};
+use app::config::ConfigCommand::{Builtin, Settings};
+use app::help::copy_value;
+use app::hooks::HookEvent;
 use app::Msg::{
     self,
     CustomCommand,
-    EnterNormalModeAndReset,
+    CustomCommandWithCount,
+    PopModeAndReset,
 };
 use app::status_bar::Msg::{
     Copy,
@@ -68,86 +81,139 @@ use app::status_bar::Msg::{
     End,
     NextChar,
     NextWord,
-    Paste,
     PasteSelection,
     PreviousChar,
     PreviousWord,
     SmartHome,
 };
-use app::ShortcutCommand::{self, Complete, Incomplete};
 use completion::completion_view::Msg::{SelectNext, SelectPrevious};
+use engine::Mapping;
+use key::Key;
+
+/// A command which can be executed again by repeat-last, with its count.
+#[derive(Clone)]
+pub enum LastCommand<COMM> {
+    /// A command line, whose aliases are expanded.
+    Line(String, Option<u32>),
+    /// A special command (i.e. a search).
+    Special(COMM, Option<u32>),
+}
+
+/// Check if the command changes the aliases, the mappings or the settings listed in the help.
+fn changes_help<COMM>(command: &ConfigCommand<COMM>) -> bool {
+    match *command {
+        Builtin(BuiltinCommand::Alias(..)) | Builtin(BuiltinCommand::Map { .. }) |
+            Builtin(BuiltinCommand::Noremap { .. }) | Builtin(BuiltinCommand::Unalias(_)) |
+            Builtin(BuiltinCommand::Unmap { .. }) => true,
+        Builtin(_) => false,
+        Settings(Map { .. }) | Settings(Set(..)) | Settings(Unmap { .. }) => true,
+        Settings(App(_)) | Settings(Custom(_)) => false,
+    }
+}
 
 impl<COMM, SETT> Mg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
           SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
 {
-    /// Convert an action String to a command String.
-    pub fn action_to_command(&self, action: &str) -> ShortcutCommand {
-        if let Some(':') = action.chars().next() {
-            if let Some(index) = action.find("<Enter>") {
-                Complete(action[1..index].to_string())
-            }
-            else {
-                Incomplete(action[1..].to_string())
-            }
-        }
-        else {
-            Complete(action.to_string())
+    /// Handle an application command.
+    /// The commands which do not use the count are executed count times.
+    fn app_command(&mut self, command: &str, count: Option<u32>) {
+        match command {
+            RECORD_MACRO => self.record_macro(),
+            REPEAT_LAST => self.repeat_last(count),
+            PASTE_HISTORY => self.paste_history(count),
+            REPLAY_MACRO => self.replay_macro_command(count),
+            _ => {
+                for _ in 0..count.unwrap_or(1).max(1) {
+                    self.repeatable_app_command(command);
+                }
+                if command != SELECT_REGISTER {
+                    self.model.selected_register = None;
+                }
+            },
         }
     }
 
-    /// Handle an application command.
-    fn app_command(&self, command: &str) {
+    /// Handle an application command which can be repeated.
+    fn repeatable_app_command(&mut self, command: &str) {
         match command {
             COMPLETE_NEXT_COMMAND => self.model.completion_view.emit(SelectNext),
             COMPLETE_PREVIOUS_COMMAND => self.model.completion_view.emit(SelectPrevious),
-            COPY => self.status_bar.emit(Copy),
-            CUT => self.status_bar.emit(Cut),
+            COPY => self.status_bar.emit(Copy(self.model.selected_register)),
+            CUT => self.status_bar.emit(Cut(self.model.selected_register)),
             ENTRY_DELETE_NEXT_CHAR => self.status_bar.emit(DeleteNextChar),
             ENTRY_DELETE_NEXT_WORD => self.status_bar.emit(DeleteNextWord),
             ENTRY_DELETE_PREVIOUS_WORD => self.status_bar.emit(DeletePreviousWord),
             ENTRY_END => self.status_bar.emit(End),
+            ENTRY_HISTORY_NEXT => self.history_next(),
+            ENTRY_HISTORY_PREVIOUS => self.history_previous(),
+            ENTRY_HISTORY_SEARCH => self.history_search(),
             ENTRY_NEXT_CHAR => self.status_bar.emit(NextChar),
             ENTRY_NEXT_WORD => self.status_bar.emit(NextWord),
             ENTRY_PREVIOUS_CHAR => self.status_bar.emit(PreviousChar),
             ENTRY_PREVIOUS_WORD => self.status_bar.emit(PreviousWord),
             ENTRY_SMART_HOME => self.status_bar.emit(SmartHome),
-            PASTE => self.status_bar.emit(Paste),
+            HELP_PAGE_DOWN => self.help_page(true),
+            HELP_PAGE_UP => self.help_page(false),
+            PASTE => self.paste_register(),
             PASTE_SELECTION => self.status_bar.emit(PasteSelection),
+            SELECT_REGISTER => self.select_register(),
             _ => unreachable!(),
         }
     }
 
     /// Call the callback with the command or show an error if the command cannot be parsed.
-    fn call_command(&mut self, command: Command<COMM>) {
+    fn call_command(&mut self, command: Command<COMM>, count: Option<u32>) {
         match command {
-            App(command) => self.app_command(&command),
-            Custom(command) => self.model.relm.stream().emit(CustomCommand(command)),
+            App(command) => self.app_command(&command, count),
+            Custom(command) => self.emit_custom_command(command, count),
             Map { action, keys, mode } => {
-                let mode_mappings = self.model.mappings.entry(self.model.modes[mode.as_str()].name)
-                    .or_insert_with(HashMap::new);
-                mode_mappings.insert(keys, action);
+                let mode_name = self.model.modes[mode.as_str()].name.clone();
+                let keys = keys.into_iter().map(Key::from).collect();
+                let mapping = Mapping::new(action, false, true, self.model.command_source);
+                self.model.engine.add_mapping(&mode_name, keys, mapping);
             },
             Set(name, value) => {
-                match SETT::to_variant(&name, value) {
-                    Ok(setting) => self.set_setting(setting),
-                    Err(error) => {
-                        self.error(Error::Msg("Error setting value".to_string()));
-                        error!("{}", error);
-                    },
+                let setting_value = copy_value(&value);
+                let changed =
+                    match self.set_builtin_setting(&name, &value) {
+                        Some(Ok(())) => true,
+                        Some(Err(error)) => {
+                            self.error(error);
+                            false
+                        },
+                        None =>
+                            match SETT::to_variant(&name, value) {
+                                Ok(setting) => {
+                                    self.set_setting(setting);
+                                    true
+                                },
+                                Err(error) => {
+                                    self.error(Error::Msg("Error setting value".to_string()));
+                                    error!("{}", error);
+                                    false
+                                },
+                            },
+                    };
+                if changed {
+                    self.remember_setting_value(&name, &setting_value);
+                    self.run_hooks(HookEvent::SettingChanged(name));
                 }
-                self.return_to_normal_mode();
             },
             Unmap { keys, mode } => {
-                let mode_mappings = self.model.mappings.entry(self.model.modes[mode.as_str()].name)
-                    .or_insert_with(HashMap::new);
-                mode_mappings.remove(&keys);
+                let mode_name = self.model.modes[mode.as_str()].name.clone();
+                let keys: Vec<Key> = keys.into_iter().map(Key::from).collect();
+                self.model.engine.remove_mapping(&mode_name, &keys);
             },
         }
     }
 
     /// Handle the command entry activate event.
     pub fn command_activate(&mut self, input: String) {
+        if self.model.history_search.is_some() {
+            self.accept_history_search();
+            return;
+        }
         let current_mode = self.model.current_mode.get();
         let message =
             if current_mode == Mode::Input || current_mode == Mode::BlockingInput {
@@ -159,14 +225,16 @@ impl<COMM, SETT> Mg<COMM, SETT>
                 }
                 self.model.choices.clear();
                 if should_reset {
-                    Some(EnterNormalModeAndReset)
+                    Some(PopModeAndReset)
                 }
                 else {
                     None
                 }
             }
             else {
-                self.handle_command(input, true, None)
+                self.add_to_history(&input);
+                let count = self.model.command_count.take();
+                self.handle_command(input, true, count)
             };
         if let Some(message) = message {
             self.model.relm.stream().emit(message);
@@ -174,15 +242,26 @@ impl<COMM, SETT> Mg<COMM, SETT>
     }
 
     /// Execute the commands and show the errors contained in the parse result.
-    pub fn execute_commands(&mut self, mut parse_result: ParseResult<COMM>, activated: bool) {
+    /// The count is given to the application commands.
+    pub fn execute_commands(&mut self, mut parse_result: ConfigResult<COMM>, activated: bool, count: Option<u32>) {
+        let help_changed = parse_result.commands.iter().any(changes_help);
         for command in parse_result.commands.drain(..) {
-            self.call_command(command);
+            match command {
+                Builtin(command) => self.builtin_command(command),
+                Settings(command) => self.call_command(command, count),
+            }
+        }
+        // The commands can change the mappings of the current mode.
+        self.update_key_handling();
+        if help_changed {
+            self.update_mapping_completer();
+            self.update_help_completer();
         }
         for error in parse_result.errors.drain(..) {
             self.show_parse_error(error);
         }
         if activated {
-            self.return_to_normal_mode();
+            self.pop_mode();
         }
     }
 
@@ -191,28 +270,66 @@ impl<COMM, SETT> Mg<COMM, SETT>
         -> Option<Msg<COMM, SETT>>
     {
         if self.is_normal_command() || !activated {
-            let parse_result = self.model.settings_parser.parse_line(&command, prefix);
-            self.execute_commands(parse_result, activated);
+            match expand_aliases(&self.model.aliases, &command) {
+                Ok(command) => self.execute_line(command, activated, prefix),
+                Err(error) => {
+                    let mut parse_result = ConfigResult::new();
+                    parse_result.errors.push(error);
+                    self.execute_commands(parse_result, activated, prefix);
+                },
+            }
         }
         else {
             // If activated is true, it means the user pressed Enter to finish the special
             // command. If it was false, that means that the user activated a command via a
             // shortcut.
-            return self.handle_special_command(Final, &command);
+            self.handle_special_command(Final, &command, prefix);
         }
         None
     }
 
+    /// Execute a command line whose aliases are expanded.
+    /// It is remembered to be executed again by repeat-last, unless it is invalid or it is repeat-last itself.
+    fn execute_line(&mut self, line: String, activated: bool, prefix: Option<u32>) {
+        let parse_result = parse_line(&mut *self.model.settings_parser, &self.model.modes, &line, prefix);
+        if parse_result.errors.is_empty() && split_command(&line).0 != REPEAT_LAST {
+            self.model.last_command = Some(LastCommand::Line(line, prefix));
+        }
+        self.execute_commands(parse_result, activated, prefix);
+    }
+
     /// Handle a special command activate or key press event.
-    pub fn handle_special_command(&mut self, activation_type: ActivationType, command: &str) -> Option<Msg<COMM, SETT>> {
+    pub fn handle_special_command(&mut self, activation_type: ActivationType, command: &str, count: Option<u32>) {
         if let Ok(special_command) = COMM::identifier_to_command(self.model.current_command_mode, command) {
             if activation_type == Final {
-                self.return_to_normal_mode();
+                self.pop_mode();
+                self.model.last_command = Some(LastCommand::Special(special_command.clone(), count));
             }
-            Some(CustomCommand(special_command))
+            self.emit_custom_command(special_command, count);
+        }
+    }
+
+    /// Send the custom command to the application, with its count if the application asked for it.
+    fn emit_custom_command(&self, command: COMM, count: Option<u32>) {
+        if self.model.commands_with_count {
+            self.model.relm.stream().emit(CustomCommandWithCount(command, count));
         }
         else {
-            None
+            self.model.relm.stream().emit(CustomCommand(command));
+        }
+    }
+
+    /// Execute the last command again.
+    /// The count replaces the count of the last command if specified.
+    fn repeat_last(&mut self, count: Option<u32>) {
+        match self.model.last_command.clone() {
+            Some(LastCommand::Line(line, last_count)) => self.execute_line(line, false, count.or(last_count)),
+            Some(LastCommand::Special(command, last_count)) => {
+                let count = count.or(last_count);
+                self.model.last_command = Some(LastCommand::Special(command.clone(), count));
+                self.emit_custom_command(command, count);
+            },
+            None => self.error(Error::Msg("No command to repeat".to_string())),
         }
     }
 
@@ -228,6 +345,9 @@ impl<COMM, SETT> Mg<COMM, SETT>
                 };
             self.error(Error::Msg(message));
         }
+        else if let Error::Msg(ref message) = error {
+            self.error(Error::Msg(message.clone()));
+        }
 
         error!("{}", error);
     }
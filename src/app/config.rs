@@ -21,16 +21,22 @@
 
 use std::collections::HashMap;
 use std::fs::{File, create_dir_all};
-use std::io::{self, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
-use mg_settings::{Config, EnumFromStr, Parser, ParseResult};
+use mg_settings::{Command, Config, EnumFromStr, Parser, ParseResult};
+use mg_settings::errors::Error;
+use mg_settings::errors::ErrorType::UnknownCommand;
 
+use app::builtin::{BuiltinCommand, expand_aliases, parse_builtin, parse_mode_mapping, split_command, INCLUDE};
+use app::key_policy::KeyPolicy;
 use app::settings::DefaultConfig;
+use key::Key;
 use {Mode, file};
 use super::{
     Modes,
     ModesHash,
+    known_mode,
     COMMAND_MODE,
     COMPLETE_NEXT_COMMAND,
     COMPLETE_PREVIOUS_COMMAND,
@@ -40,16 +46,75 @@ use super::{
     ENTRY_DELETE_NEXT_WORD,
     ENTRY_DELETE_PREVIOUS_WORD,
     ENTRY_END,
+    ENTRY_HISTORY_NEXT,
+    ENTRY_HISTORY_PREVIOUS,
+    ENTRY_HISTORY_SEARCH,
     ENTRY_NEXT_CHAR,
     ENTRY_NEXT_WORD,
     ENTRY_PREVIOUS_CHAR,
     ENTRY_PREVIOUS_WORD,
     ENTRY_SMART_HOME,
+    HELP_PAGE_DOWN,
+    HELP_PAGE_UP,
     NORMAL_MODE,
+    NORMAL_MODE_PREFIX,
+    OPERATOR_MODE,
+    OPERATOR_MODE_PREFIX,
     PASTE,
+    PASTE_HISTORY,
     PASTE_SELECTION,
+    RECORD_MACRO,
+    REPEAT_LAST,
+    REPLAY_MACRO,
+    SELECT_REGISTER,
 };
 
+/// The commands implemented by mg.
+pub const APPLICATION_COMMANDS: &[&str] = &[COMPLETE_NEXT_COMMAND, COMPLETE_PREVIOUS_COMMAND, COPY, CUT,
+    ENTRY_DELETE_NEXT_CHAR, ENTRY_DELETE_NEXT_WORD, ENTRY_DELETE_PREVIOUS_WORD, ENTRY_END, ENTRY_HISTORY_NEXT,
+    ENTRY_HISTORY_PREVIOUS, ENTRY_HISTORY_SEARCH, ENTRY_NEXT_CHAR, ENTRY_NEXT_WORD, ENTRY_PREVIOUS_CHAR,
+    ENTRY_PREVIOUS_WORD, ENTRY_SMART_HOME, HELP_PAGE_DOWN, HELP_PAGE_UP, PASTE, PASTE_HISTORY, PASTE_SELECTION,
+    RECORD_MACRO, REPEAT_LAST, REPLAY_MACRO, SELECT_REGISTER];
+
+/// A command from a config file or from the command entry.
+pub enum ConfigCommand<COMM> {
+    /// A command handled by mg itself.
+    Builtin(BuiltinCommand),
+    /// A command parsed by the settings parser.
+    Settings(Command<COMM>),
+}
+
+/// The commands and errors resulting from parsing a config.
+pub struct ConfigResult<COMM> {
+    /// The commands, in the order they appear in the config.
+    pub commands: Vec<ConfigCommand<COMM>>,
+    /// The errors encountered while parsing.
+    pub errors: Vec<Error>,
+}
+
+impl<COMM> ConfigResult<COMM> {
+    /// Create an empty result.
+    #[allow(unknown_lints, new_without_default_derive)]
+    pub fn new() -> Self {
+        ConfigResult {
+            commands: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Add the commands and errors of another result.
+    pub fn merge(&mut self, mut result: ConfigResult<COMM>) {
+        self.commands.append(&mut result.commands);
+        self.errors.append(&mut result.errors);
+    }
+
+    /// Add the commands and errors of a settings parser result.
+    fn merge_parse_result(&mut self, mut result: ParseResult<COMM>) {
+        self.commands.extend(result.commands.drain(..).map(ConfigCommand::Settings));
+        self.errors.append(&mut result.errors);
+    }
+}
+
 /// Create the default config directories and files.
 pub fn create_default_config(default_config: Vec<DefaultConfig>) -> Result<(), io::Error> {
     for config_item in default_config {
@@ -70,34 +135,345 @@ pub fn create_default_config_file(path: &Path, content: &'static str) -> Result<
     Ok(())
 }
 
+/// Get the canonical path of a file, to compare it to the files being sourced or included.
+/// The path is kept as is if the file does not exist.
+pub fn canonical_path(filename: &Path) -> PathBuf {
+    filename.canonicalize().unwrap_or_else(|_| filename.to_path_buf())
+}
+
+/// Get the path of a file included from a config file.
+/// A relative path is relative to the include path.
+pub fn include_filename(include_path: Option<&PathBuf>, filename: &Path) -> PathBuf {
+    match include_path {
+        Some(include_path) => include_path.join(filename),
+        None => filename.to_path_buf(),
+    }
+}
+
+/// A mode known by mg: a mode of mg, a mode given by the application or a mode added at runtime.
+#[derive(Clone)]
+pub struct ModeInfo {
+    pub color: Option<String>,
+    pub css_class: Option<String>,
+    pub key_policy: KeyPolicy,
+    pub label: Option<String>,
+    pub name: String,
+    pub prefix: String,
+    pub show_count: bool,
+}
+
+impl<'a> From<&'a Mode> for ModeInfo {
+    fn from(mode: &Mode) -> Self {
+        ModeInfo {
+            color: mode.color.map(str::to_string),
+            css_class: mode.css_class.map(str::to_string),
+            key_policy: mode.key_policy.clone(),
+            label: mode.label.map(str::to_string),
+            name: mode.name.to_string(),
+            prefix: mode.prefix.to_string(),
+            show_count: mode.show_count,
+        }
+    }
+}
+
+/// Check that a new mode has a valid prefix and that its name and prefix are not used by another mode.
+pub fn check_new_mode(modes: &ModesHash, name: &str, prefix: &str) -> Result<(), Error> {
+    if prefix.is_empty() || !prefix.chars().all(|character| character.is_ascii_alphabetic()) {
+        Err(Error::Msg(format!("Invalid mode prefix: {}", prefix)))
+    }
+    else if modes.contains_key(prefix) {
+        Err(Error::Msg(format!("Duplicate mode prefix: {}", prefix)))
+    }
+    else if known_mode(name) != super::Mode::Unknown || modes.values().any(|mode| mode.name == name) {
+        Err(Error::Msg(format!("Duplicate mode name: {}", name)))
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// Create a mode added at runtime.
+pub fn new_mode(name: String, prefix: String, show_count: bool) -> ModeInfo {
+    ModeInfo {
+        color: None,
+        css_class: None,
+        key_policy: KeyPolicy::PassUnmapped,
+        label: None,
+        name,
+        prefix,
+        show_count,
+    }
+}
+
 /// Parse a configuration file.
+/// The user modes with an invalid or duplicate prefix are reported as errors and ignored.
+/// Since the built-in commands are not known by the settings parser, this returns a `ConfigResult` instead of the
+/// `ParseResult` of the settings parser: its settings commands are wrapped in `ConfigCommand::Settings`.
 pub fn parse_config<P: AsRef<Path>, COMM: EnumFromStr>(filename: P, user_modes: Modes, include_path: Option<PathBuf>)
-    -> (Parser<COMM>, ParseResult<COMM>, ModesHash)
+    -> (Parser<COMM>, ConfigResult<COMM>, ModesHash)
 {
-    let mut parse_result = ParseResult::new();
-
     let mut modes = HashMap::new();
+    let normal_mode = Mode::new(NORMAL_MODE, NORMAL_MODE_PREFIX, true).key_policy(KeyPolicy::Swallow);
+    let mut mapping_modes = vec![normal_mode.prefix];
+    modes.insert(normal_mode.prefix.to_string(), ModeInfo::from(&normal_mode));
+    let command_mode = Mode::new(COMMAND_MODE, "c", false);
+    mapping_modes.push(command_mode.prefix);
+    modes.insert(command_mode.prefix.to_string(), ModeInfo::from(&command_mode));
+    // The operator-pending mode is only needed by the applications setting operators, so it gives way to a mode
+    // of the application using its prefix.
+    let operator_mode = Mode::new(OPERATOR_MODE, OPERATOR_MODE_PREFIX, true).key_policy(KeyPolicy::Swallow);
+    if !user_modes.iter().any(|mode| mode.prefix == operator_mode.prefix) {
+        mapping_modes.push(operator_mode.prefix);
+        modes.insert(operator_mode.prefix.to_string(), ModeInfo::from(&operator_mode));
+    }
+    let mut mode_errors = vec![];
     for mode in user_modes {
-        modes.insert(mode.prefix, mode.clone());
+        match check_new_mode(&modes, mode.name, mode.prefix) {
+            Ok(()) => {
+                mapping_modes.push(mode.prefix);
+                modes.insert(mode.prefix.to_string(), ModeInfo::from(mode));
+            },
+            Err(error) => mode_errors.push(error),
+        }
     }
-    assert!(modes.insert("n", Mode { name: NORMAL_MODE, prefix: "n", show_count: true }).is_none(),
-        "Duplicate mode prefix n.");
-    assert!(modes.insert("c", Mode { name: COMMAND_MODE, prefix: "c", show_count: false }).is_none(),
-        "Duplicate mode prefix c.");
     let config = Config {
-        application_commands: vec![COMPLETE_NEXT_COMMAND, COMPLETE_PREVIOUS_COMMAND, COPY, CUT, ENTRY_DELETE_NEXT_CHAR,
-            ENTRY_DELETE_NEXT_WORD, ENTRY_DELETE_PREVIOUS_WORD, ENTRY_END, ENTRY_NEXT_CHAR, ENTRY_NEXT_WORD,
-            ENTRY_PREVIOUS_CHAR, ENTRY_PREVIOUS_WORD, ENTRY_SMART_HOME, PASTE, PASTE_SELECTION],
-        mapping_modes: modes.keys().cloned().collect(),
+        application_commands: APPLICATION_COMMANDS.to_vec(),
+        mapping_modes,
     };
     let mut parser = Parser::new_with_config(config);
-    if let Some(include_path) = include_path {
-        parser.set_include_path(include_path);
+    if let Some(ref include_path) = include_path {
+        parser.set_include_path(include_path.clone());
     }
 
-    let file = file::open(&filename);
-    let file = rtry_no_return!(parse_result, file, { return (parser, parse_result, modes); });
-    let buf_reader = BufReader::new(file);
-    let parse_result = parser.parse(buf_reader, None);
+    let mut file_modes = modes.clone();
+    let mut files = vec![canonical_path(filename.as_ref())];
+    let mut parse_result = parse_file(&mut parser, &mut file_modes, &mut HashMap::new(), filename.as_ref(),
+        include_path.as_ref(), &mut files);
+    mode_errors.append(&mut parse_result.errors);
+    parse_result.errors = mode_errors;
     (parser, parse_result, modes)
 }
+
+/// Parse a config file, line by line.
+/// The built-in commands are handled here and the included files are parsed recursively.
+/// The modes defined in the file are added to the modes, so that the next lines can use their prefix, and the
+/// aliases are updated, so that the next lines starting with an alias are expanded.
+/// `files` contains the canonical paths of the files being sourced or included (including this one), so that a file
+/// including itself, directly or not, is reported instead of being parsed again.
+pub fn parse_file<COMM: EnumFromStr>(parser: &mut Parser<COMM>, modes: &mut ModesHash,
+    aliases: &mut HashMap<String, String>, filename: &Path, include_path: Option<&PathBuf>, files: &mut Vec<PathBuf>)
+    -> ConfigResult<COMM>
+{
+    let mut result = ConfigResult::new();
+    let file =
+        match file::open(filename) {
+            Ok(file) => file,
+            Err(error) => {
+                result.errors.push(error);
+                return result;
+            },
+        };
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line_number = index + 1;
+        let line =
+            match line {
+                Ok(line) => line,
+                Err(error) => {
+                    result.errors.push(error.into());
+                    break;
+                },
+            };
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+        let (command, args) = split_command(trimmed_line);
+        if command == INCLUDE {
+            if args.is_empty() {
+                result.errors.push(Error::Msg(format!("Argument required for {} on line {}", INCLUDE, line_number)));
+                continue;
+            }
+            let included_file = include_filename(include_path, Path::new(args));
+            let canonical_file = canonical_path(&included_file);
+            if files.contains(&canonical_file) {
+                result.errors.push(Error::Msg(format!("Recursive include of {} on line {}", included_file.display(),
+                    line_number)));
+                continue;
+            }
+            files.push(canonical_file);
+            let include_result = parse_file(parser, modes, aliases, &included_file, include_path, files);
+            files.pop();
+            result.merge(include_result);
+        }
+        else {
+            let mut line_result =
+                match expand_aliases(aliases, &line) {
+                    Ok(line) => parse_line(parser, modes, &line, None),
+                    Err(error) => {
+                        let mut line_result = ConfigResult::new();
+                        line_result.errors.push(error);
+                        line_result
+                    },
+                };
+            for error in &mut line_result.errors {
+                set_error_line(error, line_number);
+            }
+            for command in &line_result.commands {
+                match *command {
+                    ConfigCommand::Builtin(BuiltinCommand::Alias(ref name, ref command)) => {
+                        aliases.insert(name.clone(), command.clone());
+                    },
+                    ConfigCommand::Builtin(BuiltinCommand::DefineMode { ref name, ref prefix, show_count }) => {
+                        if check_new_mode(modes, name, prefix).is_ok() {
+                            modes.insert(prefix.clone(), new_mode(name.clone(), prefix.clone(), show_count));
+                        }
+                    },
+                    ConfigCommand::Builtin(BuiltinCommand::Unalias(ref name)) => {
+                        aliases.remove(name);
+                    },
+                    _ => (),
+                }
+            }
+            result.merge(line_result);
+        }
+    }
+    result
+}
+
+/// Parse a line from a config file or from the command entry.
+pub fn parse_line<COMM: EnumFromStr>(parser: &mut Parser<COMM>, modes: &ModesHash, line: &str,
+    prefix: Option<u32>) -> ConfigResult<COMM>
+{
+    let mut result = ConfigResult::new();
+    match parse_builtin(line, modes) {
+        Some(Ok(command)) => result.commands.push(ConfigCommand::Builtin(command)),
+        Some(Err(error)) => result.errors.push(error),
+        None => {
+            let parse_result = parser.parse_line(line, prefix);
+            // The settings parser only knows the modes given when it was created and does not know the mouse
+            // keys, the keys it has no name for and the keycode argument, so the mappings of the modes added
+            // afterwards and these mappings are parsed here.
+            match parse_mode_mapping(line) {
+                Some(mapping) if is_unknown_command(&parse_result) || needs_builtin_mapping(&mapping) =>
+                    match mapping {
+                        Ok(command) => result.commands.push(ConfigCommand::Builtin(command)),
+                        Err(error) => result.errors.push(error),
+                    },
+                _ => result.merge_parse_result(parse_result),
+            }
+        },
+    }
+    result
+}
+
+/// Give the line number in the config file to an error of a line parsed on its own.
+/// The errors of the built-in commands have no position and the errors of the settings parser are on line 1, so they
+/// are replaced by a message, since the position of a parse error is private.
+fn set_error_line(error: &mut Error, line_number: usize) {
+    let message =
+        match *error {
+            Error::Msg(ref message) => format!("{} on line {}", message, line_number),
+            Error::Parse(ref parse_error) => {
+                let message = parse_error.to_string();
+                let first_line = " on line 1, ";
+                match message.rfind(first_line) {
+                    Some(index) => format!("{} on line {}, {}", &message[..index], line_number,
+                        &message[index + first_line.len()..]),
+                    None => message,
+                }
+            },
+            Error::Setting(_) => return,
+        };
+    *error = Error::Msg(message);
+}
+
+/// Check if the mapping uses a mouse key, a key unknown to the settings parser or the keycode argument.
+fn needs_builtin_mapping(mapping: &Result<BuiltinCommand, Error>) -> bool {
+    match *mapping {
+        Ok(BuiltinCommand::Map { keycode_fallback: true, .. }) => true,
+        Ok(BuiltinCommand::Map { ref keys, .. }) | Ok(BuiltinCommand::Unmap { ref keys, .. }) =>
+            keys.iter().any(Key::is_extended),
+        _ => false,
+    }
+}
+
+/// Check if the settings parser did not recognize the command.
+fn is_unknown_command<COMM>(parse_result: &ParseResult<COMM>) -> bool {
+    parse_result.errors.iter().any(|error| {
+        match *error {
+            Error::Parse(ref error) =>
+                match error.typ {
+                    UnknownCommand => true,
+                    _ => false,
+                },
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use mg_settings::{EnumFromStr, Parser};
+
+    use super::{canonical_path, check_new_mode, new_mode, parse_file};
+
+    struct NoCommand;
+
+    impl EnumFromStr for NoCommand {
+        fn create(variant: &str, _argument: &str, _prefix: Option<u32>) -> Result<Self, String> {
+            Err(format!("Unknown command {}", variant))
+        }
+
+        fn has_argument(variant: &str) -> Result<bool, String> {
+            Err(format!("Unknown command {}", variant))
+        }
+    }
+
+    #[test]
+    fn check_mode() {
+        let mut modes = HashMap::new();
+        modes.insert("f".to_string(), new_mode("foo".to_string(), "f".to_string(), false));
+        assert!(check_new_mode(&modes, "bar", "b").is_ok());
+        assert!(check_new_mode(&modes, "bar", "ba").is_ok());
+        assert!(check_new_mode(&modes, "bar", "").is_err());
+        assert!(check_new_mode(&modes, "bar", "b1").is_err());
+        assert!(check_new_mode(&modes, "bar", "f").is_err());
+        assert!(check_new_mode(&modes, "foo", "b").is_err());
+        assert!(check_new_mode(&modes, "normal", "b").is_err());
+    }
+
+    #[test]
+    fn error_line_numbers() {
+        let filename = env::temp_dir().join("mg-test-error-line-numbers");
+        fs::write(&filename, "# comment\n\nunknown-command\ninclude\nalias rl\n").unwrap();
+        let mut parser = Parser::<NoCommand>::new();
+        let result = parse_file(&mut parser, &mut HashMap::new(), &mut HashMap::new(), &filename, None, &mut vec![]);
+        fs::remove_file(&filename).unwrap();
+        let errors: Vec<_> = result.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].ends_with("on line 3, column 1"), "{}", errors[0]);
+        assert_eq!(errors[1], "Argument required for include on line 4");
+        assert_eq!(errors[2], "Argument required for alias on line 5");
+    }
+
+    #[test]
+    fn recursive_include() {
+        let first_filename = env::temp_dir().join("mg-test-recursive-include-first");
+        let second_filename = env::temp_dir().join("mg-test-recursive-include-second");
+        fs::write(&first_filename, format!("include {}\n", second_filename.display())).unwrap();
+        fs::write(&second_filename, format!("\ninclude {}\n", first_filename.display())).unwrap();
+        let mut parser = Parser::<NoCommand>::new();
+        let mut files = vec![canonical_path(&first_filename)];
+        let result = parse_file(&mut parser, &mut HashMap::new(), &mut HashMap::new(), &first_filename, None,
+            &mut files);
+        fs::remove_file(&first_filename).unwrap();
+        fs::remove_file(&second_filename).unwrap();
+        let errors: Vec<_> = result.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(errors, vec![format!("Recursive include of {} on line 2", first_filename.display())]);
+        assert_eq!(files.len(), 1);
+    }
+}
@@ -26,7 +26,6 @@ use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
 
 use gtk;
 use mg_settings::{EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
-use mg_settings::key::Key;
 use mg_settings::settings;
 use relm::{
     ContainerComponent,
@@ -43,7 +42,7 @@ use app::Msg::{
     BlockingInput,
     BlockingQuestion,
     BlockingYesNoQuestion,
-    EnterNormalModeAndReset,
+    PopModeAndReset,
     Input,
     Question,
     ResetInput,
@@ -52,6 +51,7 @@ use app::Msg::{
 use app::status_bar::Msg::{Identifier, ShowIdentifier};
 use completion::NO_COMPLETER_IDENT;
 use completion::completion_view::Msg::SetOriginalInput;
+use key::Key;
 use self::DialogResult::{Answer, Shortcut};
 
 /// A Responder is a way to send back the answer of a dialog to the code that showed this dialog.
@@ -217,8 +217,8 @@ impl DialogBuilder {
     }
 
     /// Add a shortcut.
-    pub fn shortcut(mut self, shortcut: Key, value: &str) -> Self {
-        self.shortcuts.insert(shortcut, value.to_string());
+    pub fn shortcut<K: Into<Key>>(mut self, shortcut: K, value: &str) -> Self {
+        self.shortcuts.insert(shortcut.into(), value.to_string());
         self
     }
 }
@@ -303,7 +303,7 @@ where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
             should_reset = true;
         }
         if should_reset {
-            self.model.relm.stream().emit(EnterNormalModeAndReset);
+            self.model.relm.stream().emit(PopModeAndReset);
         }
     }
 
@@ -342,10 +342,10 @@ where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
 
         self.model.answer = String::new();
         if dialog_builder.blocking {
-            self.set_mode(BLOCKING_INPUT_MODE);
+            self.push_mode(BLOCKING_INPUT_MODE);
         }
         else {
-            self.set_mode(INPUT_MODE);
+            self.push_mode(INPUT_MODE);
         }
         if let Some(responder) = dialog_builder.responder {
             self.model.input_callback = Some(Box::new(move |answer, shortcut_pressed| {
@@ -0,0 +1,343 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Help view listing the commands, the settings and the key bindings.
+
+use std::collections::HashMap;
+
+use glib::ToValue;
+use glib::types::Type;
+use gtk;
+use gtk::{
+    prelude::GtkListStoreExtManual,
+    AdjustmentExt,
+    CellLayoutExt,
+    CellRendererText,
+    GtkListStoreExt,
+    ListStore,
+    ScrolledWindowExt,
+    TreeModelExt,
+    TreePath,
+    TreeSelectionExt,
+    TreeViewColumn,
+    TreeViewColumnExt,
+    TreeViewExt,
+    WidgetExt,
+};
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand, Value};
+use mg_settings::errors::Error;
+use relm::Widget;
+use relm_derive::widget;
+
+use app::Mg;
+use app::builtin::{BUILTIN_COMMANDS, BUILTIN_SETTINGS, HELP};
+use app::config::APPLICATION_COMMANDS;
+use completion::{self, HelpCompleter};
+use completion::completion_view::Msg::AddCompleters;
+use self::Msg::*;
+
+/// An entry of the help: a section title or an item.
+#[derive(Clone)]
+pub struct HelpEntry {
+    description: String,
+    details: String,
+    /// Whether the entry is the title of a section.
+    is_title: bool,
+    name: String,
+}
+
+impl HelpEntry {
+    fn new(name: &str, details: &str, description: &str) -> Self {
+        HelpEntry {
+            description: description.to_string(),
+            details: details.to_string(),
+            is_title: false,
+            name: name.to_string(),
+        }
+    }
+
+    fn title(title: &str) -> Self {
+        HelpEntry {
+            description: String::new(),
+            details: String::new(),
+            is_title: true,
+            name: title.to_string(),
+        }
+    }
+}
+
+#[allow(missing_docs)]
+pub struct Model {
+    visible: bool,
+}
+
+#[derive(Msg)]
+pub enum Msg {
+    Entries(Vec<HelpEntry>),
+    /// Select the entry at the index and scroll to it.
+    Jump(usize),
+    PageDown,
+    PageUp,
+    Visible(bool),
+}
+
+/// A widget showing the help.
+#[widget]
+impl Widget for HelpView {
+    fn init_view(&mut self) {
+        for index in 0..3 {
+            let view_column = TreeViewColumn::new();
+            let cell = CellRendererText::new();
+            view_column.pack_start(&cell, true);
+            view_column.add_attribute(&cell, "text", index);
+            view_column.add_attribute(&cell, "weight", 3);
+            view_column.set_expand(index == 2);
+            self.tree_view.append_column(&view_column);
+        }
+    }
+
+    fn jump(&self, index: usize) {
+        let path = TreePath::from_indicesv(&[index as i32]);
+        self.tree_view.get_selection().select_path(&path);
+        self.tree_view.scroll_to_cell(Some(&path), None::<&TreeViewColumn>, true, 0.0, 0.0);
+    }
+
+    fn model() -> Model {
+        Model {
+            visible: false,
+        }
+    }
+
+    /// Scroll by a page, down if the direction is positive.
+    fn scroll_page(&self, direction: f64) {
+        if let Some(adjustment) = self.scrolled_window.get_vadjustment() {
+            adjustment.set_value(adjustment.get_value() + direction * adjustment.get_page_size());
+        }
+    }
+
+    fn set_entries(&self, entries: Vec<HelpEntry>) {
+        let model = ListStore::new(&[Type::String, Type::String, Type::String, Type::I32]);
+        for entry in &entries {
+            // The titles are shown in bold.
+            let weight: i32 =
+                if entry.is_title {
+                    700
+                }
+                else {
+                    400
+                };
+            let row = model.insert(-1);
+            model.set_value(&row, 0, &entry.name.to_value());
+            model.set_value(&row, 1, &entry.details.to_value());
+            model.set_value(&row, 2, &entry.description.to_value());
+            model.set_value(&row, 3, &weight.to_value());
+        }
+        self.tree_view.set_model(Some(&model));
+        if let Some(iter) = model.get_iter_first() {
+            if let Some(path) = model.get_path(&iter) {
+                self.tree_view.scroll_to_cell(Some(&path), None::<&TreeViewColumn>, false, 0.0, 0.0);
+            }
+        }
+    }
+
+    fn update(&mut self, msg: Msg) {
+        match msg {
+            Entries(entries) => self.set_entries(entries),
+            Jump(index) => self.jump(index),
+            PageDown => self.scroll_page(1.0),
+            PageUp => self.scroll_page(-1.0),
+            Visible(visible) => self.model.visible = visible,
+        }
+    }
+
+    view! {
+        #[name="scrolled_window"]
+        gtk::ScrolledWindow {
+            visible: self.model.visible,
+            #[name="tree_view"]
+            gtk::TreeView {
+                can_focus: false,
+                enable_search: false,
+                headers_visible: false,
+            }
+        }
+    }
+}
+
+/// Copy a setting value, which does not implement `Clone`.
+pub fn copy_value(value: &Value) -> Value {
+    match *value {
+        Value::Bool(value) => Value::Bool(value),
+        Value::Float(value) => Value::Float(value),
+        Value::Int(value) => Value::Int(value),
+        Value::Str(ref value) => Value::Str(value.clone()),
+    }
+}
+
+/// Get the type of a setting value.
+fn value_type(value: &Value) -> &'static str {
+    match *value {
+        Value::Bool(_) => "bool",
+        Value::Float(_) => "float",
+        Value::Int(_) => "int",
+        Value::Str(_) => "string",
+    }
+}
+
+/// Convert a setting value to a string as written in the config file.
+fn value_to_string(value: &Value) -> String {
+    match *value {
+        Value::Bool(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::Str(ref value) => value.clone(),
+    }
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Show the help, scrolled to the entry of the topic if any.
+    pub fn help(&mut self, topic: Option<String>) {
+        let entries = self.help_entries();
+        let index =
+            match topic {
+                Some(topic) =>
+                    match entries.iter().position(|entry| !entry.is_title && entry.name == topic) {
+                        Some(index) => Some(index),
+                        None => {
+                            self.error(Error::Msg(format!("No help for {}", topic)));
+                            return;
+                        },
+                    },
+                None => None,
+            };
+        self.model.help_view.emit(Entries(entries));
+        if let Some(index) = index {
+            self.model.help_view.emit(Jump(index));
+        }
+        self.model.help_shown = true;
+        self.model.help_view.emit(Visible(true));
+    }
+
+    /// Get the entries of the help: the commands, the settings and the key bindings of each mode.
+    fn help_entries(&self) -> Vec<HelpEntry> {
+        let mut entries = vec![HelpEntry::title("Commands")];
+        let commands = COMM::get_metadata().into_iter()
+            .filter(|&(_, ref metadata)| !metadata.completion_hidden)
+            .map(|(name, metadata)| {
+                let kind =
+                    if metadata.is_special_command {
+                        "special command"
+                    }
+                    else {
+                        "command"
+                    };
+                (name, kind.to_string(), metadata.help_text)
+            });
+        let builtin_commands = BUILTIN_COMMANDS.iter()
+            .map(|&(name, help)| (name.to_string(), "built-in command".to_string(), help.to_string()));
+        let application_commands = APPLICATION_COMMANDS.iter()
+            .map(|&name| (name.to_string(), "built-in command".to_string(), String::new()));
+        let aliases = self.model.aliases.iter()
+            .map(|(name, command)| (name.clone(), "alias".to_string(), command.clone()));
+        let mut commands: Vec<_> = commands.chain(builtin_commands).chain(application_commands).chain(aliases)
+            .collect();
+        commands.sort();
+        entries.extend(commands.iter()
+            .map(|&(ref name, ref kind, ref help)| HelpEntry::new(name, kind, help)));
+
+        entries.push(HelpEntry::title("Settings"));
+        let mut settings: Vec<_> = SETT::get_metadata().into_iter()
+            .filter(|&(_, ref metadata)| !metadata.completion_hidden)
+            .map(|(name, metadata)| (name, metadata.help_text))
+            .chain(BUILTIN_SETTINGS.iter().map(|&(name, help)| (name.to_string(), help.to_string())))
+            .collect();
+        settings.sort();
+        for (name, help) in settings {
+            let details = self.setting_details(&name);
+            entries.push(HelpEntry::new(&name, &details, &help));
+        }
+
+        let mut mode = String::new();
+        for mapping in self.mapping_descriptions(None) {
+            if mapping.mode != mode {
+                entries.push(HelpEntry::title(&format!("Key bindings ({} mode)", mapping.mode)));
+                mode = mapping.mode.clone();
+            }
+            entries.push(HelpEntry::new(&mapping.keys, mapping.source.name(), &mapping.action));
+        }
+        entries
+    }
+
+    /// Hide the help view.
+    pub fn hide_help(&mut self) {
+        if self.model.help_shown {
+            self.model.help_shown = false;
+            self.model.help_view.emit(Visible(false));
+        }
+    }
+
+    /// Scroll the help view by a page.
+    pub fn help_page(&self, down: bool) {
+        let msg =
+            if down {
+                PageDown
+            }
+            else {
+                PageUp
+            };
+        self.model.help_view.emit(msg);
+    }
+
+    /// Remember the value of the setting set by a command, to show it in the help.
+    pub fn remember_setting_value(&mut self, name: &str, value: &Value) {
+        self.model.setting_values.insert(name.to_string(), copy_value(value));
+    }
+
+    /// Get the type, the default value and the current value of the setting.
+    /// The default value is only known for the settings of mg, since the settings of the application cannot be
+    /// read, and the current value (and thus the type) is known once a command sets it.
+    fn setting_details(&self, name: &str) -> String {
+        let default = self.model.setting_defaults.get(name);
+        let current = self.model.setting_values.get(name).or(default);
+        let mut details = current.map(value_type).unwrap_or_default().to_string();
+        if let Some(default) = default {
+            details.push_str(&format!(", default: {}", value_to_string(default)));
+        }
+        if let Some(current) = current {
+            details.push_str(&format!(", current: {}", value_to_string(current)));
+        }
+        details
+    }
+
+    /// Replace the help completer to complete the current help topics.
+    pub fn update_help_completer(&self) {
+        let topics = self.help_entries().into_iter()
+            .filter(|entry| !entry.is_title)
+            .map(|entry| (entry.name, entry.description))
+            .collect();
+        let mut completers: HashMap<_, Box<dyn completion::Completer>> = HashMap::new();
+        completers.insert(HELP, Box::new(HelpCompleter::new(topics)));
+        self.model.completion_view.emit(AddCompleters(completers));
+    }
+}
@@ -0,0 +1,356 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+
+use app::Mg;
+use app::status_bar::Msg::{Identifier, ShowIdentifier};
+
+/// The maximum number of lines kept for each command identifier.
+const HISTORY_MAX_SIZE: usize = 1000;
+
+/// The position of the user in the history while navigating with previous/next.
+struct Navigation {
+    /// The index of the entry currently shown in the command entry.
+    index: usize,
+    /// The text that was in the command entry when the navigation started.
+    /// Only the entries starting with it are shown.
+    prefix: String,
+}
+
+/// The state of an incremental reverse search in the history.
+pub struct HistorySearch {
+    /// The index of the current match.
+    index: Option<usize>,
+    /// The text of the command entry before the search started.
+    original_input: String,
+    /// The text to search for.
+    query: String,
+}
+
+/// The command-line history.
+/// The lines are kept separately for each command identifier (i.e. ':' or '/').
+pub struct History {
+    entries: HashMap<char, Vec<String>>,
+    filename: Option<PathBuf>,
+    navigation: Option<Navigation>,
+}
+
+impl History {
+    /// Create an empty history which is not saved.
+    #[allow(unknown_lints, new_without_default_derive)]
+    pub fn new() -> Self {
+        History {
+            entries: HashMap::new(),
+            filename: None,
+            navigation: None,
+        }
+    }
+
+    /// Add a line to the history of the command identifier and save the history.
+    pub fn add(&mut self, identifier: char, line: &str) -> io::Result<()> {
+        self.reset_navigation();
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        {
+            let entries = self.entries.entry(identifier).or_insert_with(Vec::new);
+            entries.retain(|entry| entry != line);
+            entries.push(line.to_string());
+            if entries.len() > HISTORY_MAX_SIZE {
+                let extra = entries.len() - HISTORY_MAX_SIZE;
+                entries.drain(..extra);
+            }
+        }
+        self.save()
+    }
+
+    /// Get the entries of the command identifier, from the oldest to the newest.
+    pub fn entries(&self, identifier: char) -> &[String] {
+        self.entries.get(&identifier)
+            .map(|entries| entries.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Load the history from the file and save the history in this file from now on.
+    pub fn load(&mut self, filename: PathBuf) -> io::Result<()> {
+        self.entries.clear();
+        self.reset_navigation();
+        if filename.exists() {
+            let file = File::open(&filename)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let mut chars = line.chars();
+                if let Some(identifier) = chars.next() {
+                    self.entries.entry(identifier).or_insert_with(Vec::new)
+                        .push(chars.as_str().to_string());
+                }
+            }
+        }
+        self.filename = Some(filename);
+        Ok(())
+    }
+
+    /// Get the next (newer) line starting with the text typed before the navigation started.
+    /// Returns this text when going past the newest line.
+    pub fn next(&mut self, identifier: char) -> Option<String> {
+        let entries = self.entries.get(&identifier)
+            .map(|entries| entries.as_slice())
+            .unwrap_or(&[]);
+        if let Some(ref mut navigation) = self.navigation {
+            let start = navigation.index + 1;
+            let found = entries.iter()
+                .enumerate()
+                .skip(start)
+                .find(|&(_, entry)| entry.starts_with(&navigation.prefix))
+                .map(|(index, _)| index);
+            match found {
+                Some(index) => {
+                    navigation.index = index;
+                    Some(entries[index].clone())
+                },
+                None => {
+                    navigation.index = entries.len();
+                    Some(navigation.prefix.clone())
+                },
+            }
+        }
+        else {
+            None
+        }
+    }
+
+    /// Get the previous (older) line starting with the current input.
+    /// The navigation starts from the current input if it is not already started.
+    pub fn previous(&mut self, identifier: char, input: &str) -> Option<String> {
+        let entries = self.entries.get(&identifier)
+            .map(|entries| entries.as_slice())
+            .unwrap_or(&[]);
+        let navigation = self.navigation.get_or_insert_with(|| Navigation {
+            index: entries.len(),
+            prefix: input.to_string(),
+        });
+        let end = navigation.index.min(entries.len());
+        let prefix = &navigation.prefix;
+        let found = entries[..end].iter()
+            .rposition(|entry| entry.starts_with(prefix.as_str()));
+        found.map(|index| {
+            navigation.index = index;
+            entries[index].clone()
+        })
+    }
+
+    /// Get the index of the newest line containing the query which is older than the line at index `before`.
+    pub fn search(&self, identifier: char, query: &str, before: usize) -> Option<usize> {
+        let entries = self.entries(identifier);
+        let end = before.min(entries.len());
+        entries[..end].iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    /// Stop the navigation, so that the next navigation starts from the newest line.
+    pub fn reset_navigation(&mut self) {
+        self.navigation = None;
+    }
+
+    /// Save the history to its file, if any.
+    fn save(&self) -> io::Result<()> {
+        if let Some(ref filename) = self.filename {
+            let mut file = File::create(filename)?;
+            for (identifier, entries) in &self.entries {
+                for entry in entries {
+                    writeln!(file, "{}{}", identifier, entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Add the activated line to the history of the current command identifier.
+    pub fn add_to_history(&mut self, input: &str) {
+        let identifier = self.model.current_command_mode;
+        if let Err(error) = self.model.history.add(identifier, input) {
+            self.error(error.into());
+        }
+    }
+
+    /// Accept the current match of the reverse search and put it in the command entry.
+    pub fn accept_history_search(&mut self) {
+        if let Some(search) = self.model.history_search.take() {
+            let identifier = self.model.current_command_mode;
+            let line =
+                match search.index {
+                    Some(index) => self.model.history.entries(identifier)[index].clone(),
+                    None => search.query,
+                };
+            self.end_history_search(&line);
+        }
+    }
+
+    /// Stop the reverse search and restore the text that was in the command entry before the search.
+    pub fn cancel_history_search(&mut self) {
+        if let Some(search) = self.model.history_search.take() {
+            self.end_history_search(&search.original_input);
+        }
+    }
+
+    /// Restore the identifier label and set the command entry text.
+    fn end_history_search(&mut self, input: &str) {
+        self.status_bar.emit(Identifier(self.model.current_command_mode.to_string()));
+        self.set_input(input);
+        self.update_completions();
+    }
+
+    /// Start a reverse search in the history or go to the next older match if the search is already started.
+    pub fn history_search(&mut self) {
+        let identifier = self.model.current_command_mode;
+        if let Some(mut search) = self.model.history_search.take() {
+            let before = search.index.unwrap_or_else(|| self.model.history.entries(identifier).len());
+            if let Some(index) = self.model.history.search(identifier, &search.query, before) {
+                search.index = Some(index);
+            }
+            self.model.history_search = Some(search);
+        }
+        else {
+            self.model.history_search = Some(HistorySearch {
+                index: None,
+                original_input: self.model.status_bar_command.clone(),
+                query: String::new(),
+            });
+            self.set_input("");
+        }
+        self.show_history_search();
+    }
+
+    /// Show the next (newer) history line in the command entry.
+    pub fn history_next(&mut self) {
+        let identifier = self.model.current_command_mode;
+        if let Some(line) = self.model.history.next(identifier) {
+            self.set_input(&line);
+            self.update_completions();
+        }
+    }
+
+    /// Show the previous (older) history line in the command entry.
+    pub fn history_previous(&mut self) {
+        let identifier = self.model.current_command_mode;
+        let input = self.model.status_bar_command.clone();
+        if let Some(line) = self.model.history.previous(identifier, &input) {
+            self.set_input(&line);
+            self.update_completions();
+        }
+    }
+
+    /// Show the search prompt with the current match in place of the identifier label.
+    fn show_history_search(&self) {
+        if let Some(ref search) = self.model.history_search {
+            let identifier = self.model.current_command_mode;
+            let label =
+                match search.index {
+                    Some(index) => format!("(reverse-i-search) {}: ", self.model.history.entries(identifier)[index]),
+                    None if search.query.is_empty() => "(reverse-i-search): ".to_string(),
+                    None => "(failed reverse-i-search): ".to_string(),
+                };
+            self.status_bar.emit(Identifier(label));
+            self.status_bar.emit(ShowIdentifier);
+        }
+    }
+
+    /// Load the history from the file and use this file to save the history.
+    pub fn set_history_file(&mut self, filename: io::Result<PathBuf>) {
+        let result = filename.and_then(|filename| self.model.history.load(filename));
+        if let Err(error) = result {
+            self.error(error.into());
+        }
+    }
+
+    /// Search the newest line containing the text typed by the user.
+    pub fn update_history_search(&mut self, query: &str) {
+        let identifier = self.model.current_command_mode;
+        let index =
+            if query.is_empty() {
+                None
+            }
+            else {
+                let len = self.model.history.entries(identifier).len();
+                self.model.history.search(identifier, query, len)
+            };
+        if let Some(ref mut search) = self.model.history_search {
+            search.index = index;
+            search.query = query.to_string();
+        }
+        self.show_history_search();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::new();
+        for line in lines {
+            history.add(':', line).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn add_lines() {
+        let history = history(&["open foo", " ", "quit", "open foo"]);
+        assert_eq!(history.entries(':'), &["quit".to_string(), "open foo".to_string()]);
+        assert!(history.entries('/').is_empty());
+    }
+
+    #[test]
+    fn navigate_with_prefix() {
+        let mut history = history(&["open foo", "quit", "open bar"]);
+        assert_eq!(history.next(':'), None);
+        assert_eq!(history.previous(':', "op"), Some("open bar".to_string()));
+        assert_eq!(history.previous(':', "open bar"), Some("open foo".to_string()));
+        assert_eq!(history.previous(':', "open foo"), None);
+        assert_eq!(history.next(':'), Some("open bar".to_string()));
+        assert_eq!(history.next(':'), Some("op".to_string()));
+        history.reset_navigation();
+        assert_eq!(history.previous(':', ""), Some("open bar".to_string()));
+        assert_eq!(history.previous(':', "open bar"), Some("quit".to_string()));
+    }
+
+    #[test]
+    fn search_lines() {
+        let history = history(&["open foo", "quit", "open bar"]);
+        assert_eq!(history.search(':', "open", 3), Some(2));
+        assert_eq!(history.search(':', "open", 2), Some(0));
+        assert_eq!(history.search(':', "open", 0), None);
+        assert_eq!(history.search(':', "foo", 100), Some(0));
+        assert_eq!(history.search(':', "write", 3), None);
+    }
+}
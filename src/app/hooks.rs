@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Commands executed when an event happens, defined with the `on` command.
+
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+use mg_settings::errors::{Error, Result};
+
+use app::Mg;
+use app::builtin::split_command;
+use self::HookEvent::*;
+
+const APP_CLOSE: &str = "app-close";
+const MODE_ENTER: &str = "mode-enter";
+const MODE_LEAVE: &str = "mode-leave";
+const SETTING_CHANGED: &str = "setting-changed";
+const STARTUP: &str = "startup";
+
+/// The pattern matching any mode or setting.
+const ANY: &str = "*";
+
+/// An event to which a command can be bound.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HookEvent {
+    /// The window is being closed.
+    AppClose,
+    /// A mode (or any mode with `*`) is entered.
+    ModeEnter(String),
+    /// A mode (or any mode with `*`) is left.
+    ModeLeave(String),
+    /// A setting (or any setting with `*`) is changed with the set command.
+    /// Only the hooks bound to any setting run when the application changes a setting with `SetSetting`, since the
+    /// name of the setting is unknown then.
+    SettingChanged(String),
+    /// The config file was executed at startup.
+    Startup,
+}
+
+impl HookEvent {
+    /// Check if the event bound to a command (with possibly `*` as its pattern) matches the event which happened.
+    fn matches(&self, event: &HookEvent) -> bool {
+        match (self, event) {
+            (&ModeEnter(ref pattern), &ModeEnter(ref name)) | (&ModeLeave(ref pattern), &ModeLeave(ref name)) |
+                (&SettingChanged(ref pattern), &SettingChanged(ref name)) => pattern == ANY || pattern == name,
+            _ => self == event,
+        }
+    }
+}
+
+/// A command bound to an event.
+pub struct Hook {
+    command: String,
+    event: HookEvent,
+}
+
+/// Parse the arguments of the `on` command: the event, its mode or setting if needed, and the command.
+pub fn parse_hook(args: &str) -> Result<(HookEvent, String)> {
+    let (event, rest) = split_command(args);
+    let (event, command) =
+        match event {
+            APP_CLOSE => (AppClose, rest),
+            MODE_ENTER | MODE_LEAVE | SETTING_CHANGED => {
+                let (name, command) = split_command(rest);
+                let name = name.to_string();
+                let event =
+                    match event {
+                        MODE_ENTER => ModeEnter(name),
+                        MODE_LEAVE => ModeLeave(name),
+                        _ => SettingChanged(name),
+                    };
+                (event, command)
+            },
+            STARTUP => (Startup, rest),
+            "" => return Err(Error::Msg("Argument required for on".to_string())),
+            _ => return Err(Error::Msg(format!("Unknown event: {}", event))),
+        };
+    let command = command.trim_end();
+    if command.is_empty() {
+        return Err(Error::Msg("Command required for on".to_string()));
+    }
+    Ok((event, command.to_string()))
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Bind the command to the event.
+    pub fn add_hook(&mut self, event: HookEvent, command: String) {
+        self.model.hooks.push(Hook {
+            command,
+            event,
+        });
+    }
+
+    /// Execute the commands bound to the event.
+    /// The events happening while executing these commands do not execute other commands, to avoid infinite loops.
+    pub fn run_hooks(&mut self, event: HookEvent) {
+        if self.model.running_hooks {
+            return;
+        }
+        let commands: Vec<_> = self.model.hooks.iter()
+            .filter(|hook| hook.event.matches(&event))
+            .map(|hook| hook.command.clone())
+            .collect();
+        if commands.is_empty() {
+            return;
+        }
+        self.model.running_hooks = true;
+        // The commands of the hooks are not the ones the user wants to repeat.
+        let last_command = self.model.last_command.take();
+        for command in commands {
+            if let Some(msg) = self.handle_command(command, false, None) {
+                self.model.relm.stream().emit(msg);
+            }
+        }
+        self.model.last_command = last_command;
+        self.model.running_hooks = false;
+    }
+
+    /// Execute the commands bound to the change of any setting.
+    pub fn run_any_setting_hooks(&mut self) {
+        self.run_hooks(SettingChanged(ANY.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HookEvent::{AppClose, ModeEnter, ModeLeave, SettingChanged, Startup};
+    use super::{ANY, parse_hook};
+
+    #[test]
+    fn match_events() {
+        assert!(ModeEnter(ANY.to_string()).matches(&ModeEnter("insert".to_string())));
+        assert!(ModeEnter("insert".to_string()).matches(&ModeEnter("insert".to_string())));
+        assert!(!ModeEnter("insert".to_string()).matches(&ModeEnter("normal".to_string())));
+        assert!(!ModeEnter("insert".to_string()).matches(&ModeLeave("insert".to_string())));
+        // The change of a setting whose name is unknown only matches the hooks of any setting.
+        assert!(SettingChanged(ANY.to_string()).matches(&SettingChanged(ANY.to_string())));
+        assert!(!SettingChanged("title".to_string()).matches(&SettingChanged(ANY.to_string())));
+        assert!(AppClose.matches(&AppClose));
+        assert!(!Startup.matches(&AppClose));
+    }
+
+    #[test]
+    fn parse_hooks() {
+        assert_eq!(parse_hook("app-close quit").ok(), Some((AppClose, "quit".to_string())));
+        assert_eq!(parse_hook("mode-enter insert set hint-chars asdf ").ok(),
+            Some((ModeEnter("insert".to_string()), "set hint-chars asdf".to_string())));
+        assert_eq!(parse_hook("setting-changed * echo changed").ok(),
+            Some((SettingChanged(ANY.to_string()), "echo changed".to_string())));
+        assert!(parse_hook("").is_err());
+        assert!(parse_hook("startup").is_err());
+        assert!(parse_hook("mode-leave normal").is_err());
+        assert!(parse_hook("unknown-event quit").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! What happens to the keys typed in the user modes: whether they reach the widget which has the focus.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+use gdk::EventKey;
+use gtk::Inhibit;
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+use mg_settings::errors::Error;
+
+use app::{Mg, Mode, ModesHash};
+use key::Key::{self, Escape};
+use key_converter::{gdk_key_to_key, gdk_keycode_to_key};
+use key_parser::parse_keys;
+use self::KeyPolicy::{PassUnmapped, Passthrough, Swallow};
+
+const PASS_UNMAPPED: &str = "pass-unmapped";
+const PASSTHROUGH: &str = "passthrough";
+const SWALLOW: &str = "swallow";
+
+/// What happens to the keys typed in a user mode.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyPolicy {
+    /// The keys which do not start a mapping of the mode reach the widget which has the focus.
+    PassUnmapped,
+    /// All the keys reach the widget which has the focus, except the specified key (i.e. `Key::Escape`) which
+    /// leaves the mode.
+    Passthrough(Key),
+    /// The keys never reach the widget which has the focus.
+    Swallow,
+}
+
+/// Parse the key which leaves a passthrough mode.
+fn parse_escape_key(keys: &str) -> Result<Key, Error> {
+    let mut keys = parse_keys(keys)?;
+    if keys.len() == 1 {
+        Ok(keys.remove(0))
+    }
+    else {
+        Err(Error::Msg(format!("Expecting a single key to leave the passthrough mode, found: {}", keys.len())))
+    }
+}
+
+/// Get the key policy of the modes, as specified in their definition.
+pub fn key_policies(modes: &ModesHash) -> HashMap<String, KeyPolicy> {
+    modes.values()
+        .map(|mode| (mode.name.clone(), mode.key_policy.clone()))
+        .collect()
+}
+
+/// Parse the arguments of the mode-keys command, after the mode name.
+pub fn parse_key_policy(args: &str) -> Result<KeyPolicy, Error> {
+    let mut args = args.split_whitespace();
+    match (args.next(), args.next(), args.next()) {
+        (Some(PASS_UNMAPPED), None, None) => Ok(PassUnmapped),
+        (Some(PASSTHROUGH), Some(key), None) => parse_escape_key(key).map(Passthrough),
+        (Some(SWALLOW), None, None) => Ok(Swallow),
+        _ => Err(Error::Msg(format!("Expecting {}, {} <key> or {}", PASS_UNMAPPED, PASSTHROUGH, SWALLOW))),
+    }
+}
+
+/// The current mode, shared with the key press event handler which decides if the keys reach the widget which
+/// has the focus.
+pub struct CurrentMode {
+    policy: RefCell<KeyPolicy>,
+    /// The first key of each mapping of the current mode which can be typed at its position on a US layout.
+    keycode_first_keys: RefCell<HashSet<Key>>,
+    /// The first key of each mapping of the current mode.
+    mapping_first_keys: RefCell<HashSet<Key>>,
+    mode: Cell<Mode>,
+    shortcut_pending: Cell<bool>,
+    /// Whether the next key is the name of a register, which is never given to the widgets.
+    waiting_for_register: Cell<bool>,
+}
+
+impl CurrentMode {
+    pub fn new(mode: Mode) -> Self {
+        CurrentMode {
+            policy: RefCell::new(PassUnmapped),
+            keycode_first_keys: RefCell::new(HashSet::new()),
+            mapping_first_keys: RefCell::new(HashSet::new()),
+            mode: Cell::new(mode),
+            shortcut_pending: Cell::new(false),
+            waiting_for_register: Cell::new(false),
+        }
+    }
+
+    pub fn get(&self) -> Mode {
+        self.mode.get()
+    }
+
+    /// Get the key policy of the current mode.
+    pub fn policy(&self) -> KeyPolicy {
+        self.policy.borrow().clone()
+    }
+
+    /// Check if the key should be inhibitted in a user mode.
+    pub fn inhibit_user_mode_key(&self, event: &EventKey) -> Inhibit {
+        let keycode_key_mapped = self.is_keycode_key_mapped(gdk_keycode_to_key(event).as_ref());
+        let key = gdk_key_to_key(event);
+        let should_inhibit =
+            match *self.policy.borrow() {
+                PassUnmapped =>
+                    match key {
+                        Some(key) => key == Escape || self.shortcut_pending.get() || keycode_key_mapped ||
+                            self.mapping_first_keys.borrow().contains(&key),
+                        None => keycode_key_mapped,
+                    },
+                Passthrough(ref escape_key) => key.as_ref() == Some(escape_key),
+                Swallow => key.is_some(),
+            };
+        Inhibit(should_inhibit)
+    }
+
+    /// Check if the key at the position of the key typed on a US layout starts a mapping which allows it.
+    fn is_keycode_key_mapped(&self, keycode_key: Option<&Key>) -> bool {
+        keycode_key.map_or(false, |key| self.keycode_first_keys.borrow().contains(key))
+    }
+
+    /// Check if the mouse key starts a mapping of the current mode or continues the keys typed.
+    pub fn is_mouse_key_mapped(&self, key: &Key) -> bool {
+        self.shortcut_pending.get() || self.mapping_first_keys.borrow().contains(key)
+    }
+
+    pub fn set(&self, mode: Mode) {
+        self.mode.set(mode);
+    }
+
+    /// Set the key policy and the mappings of the current mode.
+    pub fn set_key_policy(&self, policy: KeyPolicy, mapping_first_keys: HashSet<Key>,
+        keycode_first_keys: HashSet<Key>)
+    {
+        *self.policy.borrow_mut() = policy;
+        *self.keycode_first_keys.borrow_mut() = keycode_first_keys;
+        *self.mapping_first_keys.borrow_mut() = mapping_first_keys;
+    }
+
+    /// Set whether the keys typed are the start of a mapping.
+    pub fn set_shortcut_pending(&self, pending: bool) {
+        self.shortcut_pending.set(pending);
+    }
+
+    /// Set whether the next key is the name of a register.
+    pub fn set_waiting_for_register(&self, waiting: bool) {
+        self.waiting_for_register.set(waiting);
+    }
+
+    /// Check if the next key is the name of a register.
+    pub fn waiting_for_register(&self) -> bool {
+        self.waiting_for_register.get()
+    }
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Check if the key is handled in the current user mode.
+    /// In a passthrough mode, only the key leaving the mode is handled: it goes back to the previous mode.
+    pub fn handle_user_mode_key(&mut self, key: &Key) -> bool {
+        if let Passthrough(escape_key) = self.model.current_mode.policy() {
+            if *key == escape_key {
+                self.pop_mode();
+            }
+            return false;
+        }
+        true
+    }
+
+    /// Share the key policy and the mappings of the current mode with the key press event handler.
+    pub fn update_key_handling(&self) {
+        let policy = self.model.key_policies.get(self.model.engine.current_mode()).cloned().unwrap_or(PassUnmapped);
+        let mapping_first_keys = self.model.engine.current_mappings()
+            .map(|mappings| mappings.first_keys().cloned().collect())
+            .unwrap_or_default();
+        let keycode_fallback = self.model.engine.keycode_fallback();
+        let keycode_first_keys = self.model.engine.current_mappings()
+            .map(|mappings| mappings.entries().into_iter()
+                .filter(|&(_, mapping)| keycode_fallback || mapping.keycode_fallback)
+                .filter_map(|(keys, _)| keys.first().cloned())
+                .collect())
+            .unwrap_or_default();
+        self.model.current_mode.set_key_policy(policy, mapping_first_keys, keycode_first_keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use key::Key::{Escape, Shift};
+    use super::KeyPolicy::{PassUnmapped, Passthrough, Swallow};
+    use super::parse_key_policy;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(parse_key_policy("pass-unmapped").ok(), Some(PassUnmapped));
+        assert_eq!(parse_key_policy("passthrough <Esc>").ok(), Some(Passthrough(Escape)));
+        assert_eq!(parse_key_policy("passthrough <S-Esc>").ok(), Some(Passthrough(Shift(Box::new(Escape)))));
+        assert_eq!(parse_key_policy("swallow").ok(), Some(Swallow));
+        assert!(parse_key_policy("").is_err());
+        assert!(parse_key_policy("passthrough").is_err());
+        assert!(parse_key_policy("passthrough ab").is_err());
+        assert!(parse_key_policy("swallow <Esc>").is_err());
+        assert!(parse_key_policy("unknown").is_err());
+    }
+}
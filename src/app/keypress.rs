@@ -19,7 +19,6 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::cell::Cell;
 use std::rc::Rc;
 
 use gdk::EventKey;
@@ -34,8 +33,11 @@ use mg_settings::{
 };
 
 use app::{Mg, Mode};
+use app::key_policy::CurrentMode;
 use app::ActivationType::Current;
-use app::Msg::{self, EnterNormalModeAndReset};
+use app::Msg::{self, FeedNextKey, PopModeAndReset};
+use key::Key;
+use key_converter::gdk_key_to_key;
 
 impl<COMM, SETT> Mg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
@@ -45,24 +47,40 @@ impl<COMM, SETT> Mg<COMM, SETT>
     #[allow(non_upper_case_globals)]
     fn command_key_press(&mut self, key: &EventKey) -> Option<Msg<COMM, SETT>> {
         match key.get_keyval() {
-            Escape => Some(EnterNormalModeAndReset),
-            _ => self.handle_shortcut(key),
+            Escape => {
+                if self.model.history_search.is_some() {
+                    self.cancel_history_search();
+                    None
+                }
+                else {
+                    Some(PopModeAndReset)
+                }
+            },
+            _ => {
+                self.handle_shortcut(key);
+                None
+            },
         }
     }
 
     /// Handle the key release event for the command mode.
     fn command_key_release(&mut self, _key: &EventKey) -> Option<Msg<COMM, SETT>> {
+        self.update_incremental_command()
+    }
+
+    /// Execute the current special command if it is incremental.
+    pub fn update_incremental_command(&mut self) -> Option<Msg<COMM, SETT>> {
         if !self.is_normal_command() && COMM::is_incremental(self.model.current_command_mode) {
             let command = self.model.status_bar_command.clone(); // TODO: remove this useless clone.
-            let msg = self.handle_special_command(Current, &command);
-            return msg;
+            let count = self.model.command_count;
+            self.handle_special_command(Current, &command, count);
         }
         None
     }
 
     /// Check if the key should be inhibitted for the command mode.
     #[allow(non_upper_case_globals)]
-    fn inhibit_command_key_press(current_mode: &Rc<Cell<Mode>>, key: &EventKey) -> Inhibit {
+    fn inhibit_command_key_press(current_mode: &Rc<CurrentMode>, key: &EventKey) -> Inhibit {
         match key.get_keyval() {
             Escape => Inhibit(false),
             _ => Self::inhibit_handle_shortcut(current_mode, key),
@@ -71,20 +89,30 @@ impl<COMM, SETT> Mg<COMM, SETT>
 
     /// Check if the key should be inhibitted for the input mode.
     #[allow(non_upper_case_globals)]
-    fn inhibit_input_key_press(current_mode: &Rc<Cell<Mode>>, key: &EventKey) -> Inhibit {
+    fn inhibit_input_key_press(current_mode: &Rc<CurrentMode>, key: &EventKey) -> Inhibit {
         match key.get_keyval() {
             Escape => Inhibit(false),
             _ => Self::inhibit_handle_shortcut(current_mode, key),
         }
     }
 
+    /// Check if the mouse button press or the scroll should be inhibitted, which is when it is mapped.
+    pub fn inhibit_mouse_key(current_mode: &Rc<CurrentMode>, key: Option<Key>) -> Inhibit {
+        Inhibit(key.map_or(false, |key| current_mode.is_mouse_key_mapped(&key)))
+    }
+
     /// Check if the key should be inhibitted.
-    pub fn inhibit_key_press(current_mode: &Rc<Cell<Mode>>, key: &EventKey) -> Inhibit {
+    pub fn inhibit_key_press(current_mode: &Rc<CurrentMode>, key: &EventKey) -> Inhibit {
+        // The register name is not inserted in the command entry.
+        if current_mode.waiting_for_register() {
+            return Inhibit(true);
+        }
         match current_mode.get() {
             Mode::Normal => Self::inhibit_normal_key_press(current_mode, key),
             Mode::Command => Self::inhibit_command_key_press(current_mode, key),
             Mode::BlockingInput | Mode::Input => Self::inhibit_input_key_press(current_mode, key),
-            _ => Self::inhibit_handle_shortcut(current_mode, key)
+            Mode::Operator => Inhibit(true),
+            _ => current_mode.inhibit_user_mode_key(key),
         }
     }
 
@@ -96,7 +124,7 @@ impl<COMM, SETT> Mg<COMM, SETT>
                 if let Some(callback) = self.model.input_callback.take() {
                     callback(None, self.model.shortcut_pressed);
                 }
-                Some(EnterNormalModeAndReset)
+                Some(PopModeAndReset)
             },
             keyval => {
                 if self.handle_input_shortcut(key) {
@@ -108,27 +136,67 @@ impl<COMM, SETT> Mg<COMM, SETT>
                         return None;
                     }
                 }
-                self.handle_shortcut(key)
+                self.handle_shortcut(key);
+                None
             },
         }
     }
 
     /// Handle the key press event.
     pub fn key_press(&mut self, key: &EventKey) {
+        self.model.fed_key_pressed = self.take_fed_key_press(key);
+        if let Some(converted_key) = gdk_key_to_key(key) {
+            // The fed keys are not recorded, since the keys which fed them were.
+            if !self.model.fed_key_pressed {
+                self.record_key(&converted_key);
+            }
+            if self.handle_register_name(&converted_key) {
+                return;
+            }
+        }
         let msg =
             match self.model.current_mode.get() {
                 Mode::Normal => self.normal_key_press(key),
                 Mode::Command => self.command_key_press(key),
                 Mode::BlockingInput | Mode::Input => self.input_key_press(key),
-                _ => self.handle_shortcut(key)
+                Mode::Operator => {
+                    self.handle_shortcut(key);
+                    None
+                },
+                _ => self.user_mode_key_press(key),
             };
         if let Some(msg) = msg {
             self.model.relm.stream().emit(msg);
         }
     }
 
+    /// Handle the key press event for the user modes.
+    fn user_mode_key_press(&mut self, key: &EventKey) -> Option<Msg<COMM, SETT>> {
+        match gdk_key_to_key(key) {
+            Some(ref converted_key) if !self.handle_user_mode_key(converted_key) => None,
+            _ => {
+                self.handle_shortcut(key);
+                None
+            },
+        }
+    }
+
+    /// Handle a mouse button press or a scroll, converted to a key.
+    /// It is only handled when it is mapped, so that the clicks reach the widgets otherwise.
+    pub fn mouse_key_press(&mut self, key: Option<Key>) {
+        if let Some(key) = key {
+            if self.model.current_mode.is_mouse_key_mapped(&key) {
+                self.record_key(&key);
+                self.handle_shortcut_key(key);
+            }
+        }
+    }
+
     /// Handle the key release event.
     pub fn key_release(&mut self, key: &EventKey) {
+        if self.take_fed_key_release(key) {
+            self.model.relm.stream().emit(FeedNextKey);
+        }
         let msg =
             match self.model.current_mode.get() {
                 Mode::Command => self.command_key_release(key),
@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Mappings whose action is a key sequence, replayed through the key handling of the current mode.
+
+use std::collections::{HashMap, HashSet};
+
+use gdk::{EventKey, EventType};
+use gtk::WidgetExt;
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+
+use app::Mg;
+use app::builtin::{BUILTIN_COMMANDS, MAP, NOREMAP};
+use app::config::APPLICATION_COMMANDS;
+use app::Msg::{FeedNextKey, ShowMappings};
+use completion::{self, MappingCompleter};
+use completion::completion_view::Msg::AddCompleters;
+use engine::MappingSource;
+use key::Key;
+use key_converter::key_to_gdk_key;
+use key_parser::{keys_to_string, parse_keys};
+
+/// A mapping, as listed to the user or given to the application.
+#[derive(Clone)]
+pub struct MappingDescription {
+    /// The command or the keys to type.
+    pub action: String,
+    /// Whether the keys can be typed at their position on a US layout.
+    pub keycode_fallback: bool,
+    /// The keys of the mapping, written in the mapping notation (i.e. `<C-x>a`).
+    pub keys: String,
+    /// The name of the mode of the mapping.
+    pub mode: String,
+    /// Whether the keys of the action can trigger other key sequence mappings.
+    pub recursive: bool,
+    pub source: MappingSource,
+}
+
+/// Check if the key event is the event which was sent, and forget the sent event if it is.
+fn take_sent_event(sent_event: &mut Option<EventKey>, event: &EventKey) -> bool {
+    let is_sent_event =
+        match *sent_event {
+            Some(ref sent_event) =>
+                sent_event.get_keyval() == event.get_keyval() &&
+                    sent_event.get_hardware_keycode() == event.get_hardware_keycode() &&
+                    sent_event.get_state() == event.get_state(),
+            None => false,
+        };
+    if is_sent_event {
+        *sent_event = None;
+    }
+    is_sent_event
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Type the keys the specified number of times before the keys which are still being fed.
+    /// When remap is false, the keys do not trigger the key sequence mappings.
+    pub fn feed_keys(&mut self, keys: Vec<Key>, count: u32, remap: bool) {
+        let actions = self.model.engine.feed_keys(keys, count, remap);
+        self.execute_actions(actions);
+    }
+
+    /// Ask the engine for the next fed key, once the previous one is handled.
+    pub fn feed_next_key(&mut self) {
+        let actions = self.model.engine.next_fed_key();
+        self.execute_actions(actions);
+    }
+
+    /// Type the keys written in the mapping notation (i.e. `3gg:open foo<Enter>`) as if the user typed them.
+    /// They are typed after the keys which are still being fed.
+    pub fn feed_typed_keys(&mut self, keys: &str) {
+        match parse_keys(keys) {
+            Ok(keys) => {
+                let actions = self.model.engine.type_keys(keys);
+                self.execute_actions(actions);
+            },
+            Err(error) => self.error(error),
+        }
+    }
+
+    /// Give the fed key to the engine when it reaches the shortcut handling of the current mode.
+    pub fn handle_fed_key(&mut self, key: Key) {
+        let actions = self.model.engine.fed_key(key);
+        self.execute_actions(actions);
+    }
+
+    /// Check if the key press event is the one sent to type a fed key, and forget it if it is.
+    pub fn take_fed_key_press(&mut self, event: &EventKey) -> bool {
+        take_sent_event(&mut self.model.fed_key_press, event)
+    }
+
+    /// Check if the key release event is the one sent to type a fed key, and forget it if it is.
+    pub fn take_fed_key_release(&mut self, event: &EventKey) -> bool {
+        take_sent_event(&mut self.model.fed_key_release, event)
+    }
+
+    /// Handle the key given by the `TypeKey` message by sending its key press and release events to the window,
+    /// so that it goes through the same handlers as the keys typed by the user.
+    /// The events are remembered to recognize them, since the events sent by other programs look the same.
+    /// The next key is fed when the key release event is handled, so that the messages emitted while handling this
+    /// key (i.e. to go back to the normal mode after answering a dialog) are handled before the next key.
+    pub fn type_fed_key(&mut self, key: Key) {
+        if let Some(window) = self.window.get_window() {
+            let press = key_to_gdk_key(&key, &window, EventType::KeyPress);
+            let release = key_to_gdk_key(&key, &window, EventType::KeyRelease);
+            if let (Some(press), Some(release)) = (press, release) {
+                self.model.fed_key_press = Some(press.clone());
+                self.model.fed_key_release = Some(release.clone());
+                self.window.event(&press);
+                self.window.event(&release);
+                return;
+            }
+        }
+        // The mouse keys have no key event.
+        self.handle_fed_key(key);
+        self.model.relm.stream().emit(FeedNextKey);
+    }
+
+    /// Show the mappings in the completion view, keeping only the mappings of the mode with this name or prefix.
+    pub fn list_mappings(&mut self, mode: Option<String>) {
+        let filter =
+            match mode {
+                Some(mode) => {
+                    let prefix_mode_name = self.model.modes.get(mode.as_str()).map(|mode| mode.name.to_string());
+                    let name =
+                        match prefix_mode_name {
+                            Some(name) => name,
+                            None if self.check_mode_name(&mode) => mode,
+                            None => return,
+                        };
+                    format!("{} ", name)
+                },
+                None => String::new(),
+            };
+        // The command entry is hidden after the execution of the command, so it is shown again afterwards.
+        self.model.relm.stream().emit(ShowMappings(format!("{} {}", MAP, filter)));
+    }
+
+    /// Get the mappings of all the modes or of the mode with this name, sorted by mode and keys.
+    pub fn mapping_descriptions(&self, mode: Option<&str>) -> Vec<MappingDescription> {
+        let mut descriptions: Vec<_> = self.model.engine.mappings().iter()
+            .filter(|&(mode_name, _)| mode.map_or(true, |mode| mode == mode_name))
+            .flat_map(|(mode, mappings)| {
+                mappings.entries().into_iter().map(move |(keys, mapping)| MappingDescription {
+                    action: mapping.action.clone(),
+                    keycode_fallback: mapping.keycode_fallback,
+                    keys: keys_to_string(&keys),
+                    mode: mode.to_string(),
+                    recursive: mapping.recursive,
+                    source: mapping.source,
+                })
+            })
+            .collect();
+        descriptions.sort_by(|description1, description2|
+            (&description1.mode, &description1.keys).cmp(&(&description2.mode, &description2.keys)));
+        descriptions
+    }
+
+    /// Enter the command to list the mappings and show them in the completion view.
+    pub fn show_mappings(&mut self, command: String) {
+        self.update_mapping_completer();
+        self.input_command(command);
+        self.show_completion();
+    }
+
+    /// Replace the mapping completer to complete the current mappings.
+    pub fn update_mapping_completer(&self) {
+        let mut completers: HashMap<_, Box<dyn completion::Completer>> = HashMap::new();
+        completers.insert(MAP, Box::new(MappingCompleter::new(self.mapping_descriptions(None))));
+        self.model.completion_view.emit(AddCompleters(completers));
+    }
+
+    /// Give the names of the commands to the engine, so that the recursive mappings starting with one of them
+    /// execute it.
+    pub fn update_command_names(&mut self) {
+        let mut names: HashSet<String> = COMM::get_metadata().into_iter().map(|(name, _)| name).collect();
+        names.extend(APPLICATION_COMMANDS.iter().map(|name| name.to_string()));
+        names.extend(BUILTIN_COMMANDS.iter().map(|&(name, _)| name.to_string()));
+        names.extend(self.model.modes.keys().map(|prefix| format!("{}{}", prefix, NOREMAP)));
+        names.extend(self.model.aliases.keys().cloned());
+        self.model.engine.set_command_names(names);
+    }
+}
@@ -20,23 +20,31 @@
  */
 
 mod app_completion;
+mod builtin;
 mod color;
 mod command;
 mod config;
 pub mod dialog;
+mod help;
+mod history;
+mod hooks;
+mod key_policy;
 mod keypress;
+mod mapping;
+mod operator;
+mod registers;
 pub mod settings;
 mod shortcut;
 pub mod status_bar;
+mod which_key;
 
-use std::cell::Cell;
 use std::char;
 use std::collections::HashMap;
 use std::io;
 use std::path::PathBuf;
 use std::rc::Rc;
 
-use gdk::{EventKey, RGBA};
+use gdk::{EventButton, EventKey, EventMask, EventScroll, RGBA};
 use gdk::keys::constants::{Escape, colon};
 use gtk;
 use gtk::{
@@ -57,10 +65,9 @@ use mg_settings::{
     Parser,
     SettingCompletion,
     SpecialCommand,
+    Value,
 };
-use mg_settings::ParseResult;
 use mg_settings::errors;
-use mg_settings::key::Key;
 use relm::{
     Component,
     Relm,
@@ -70,9 +77,21 @@ use relm::{
 };
 use relm_derive::widget;
 
-use app::config::create_default_config;
-pub use app::config::parse_config;
+use app::builtin::builtin_setting_defaults;
+pub use app::builtin::{BuiltinCommand, BUILTIN_COMMANDS, BUILTIN_SETTINGS};
+use app::command::LastCommand;
+use app::config::{ModeInfo, create_default_config};
+pub use app::config::{ConfigCommand, ConfigResult, parse_config};
 use app::dialog::Responder;
+use app::help::HelpView;
+use app::history::{History, HistorySearch};
+use app::hooks::Hook;
+pub use app::hooks::HookEvent;
+use app::key_policy::{CurrentMode, key_policies};
+pub use app::key_policy::KeyPolicy;
+pub use app::mapping::MappingDescription;
+use app::operator::parse_key_names;
+use app::registers::{Recording, RegisterCommand, Registers};
 use app::settings::DefaultConfig;
 use app::shortcut::shortcut_to_string;
 use completion::{
@@ -87,7 +106,11 @@ use completion::completion_view::Msg::{
     CompletionChange,
     Visible,
 };
-use self::color::{color_blue, color_orange, color_red};
+use engine::{Engine, Timer, BLOCKING_INPUT_MODE, COMMAND_MODE, INPUT_MODE, NORMAL_MODE, OPERATOR_MODE};
+pub use engine::{MappingSource, Operation};
+use key::Key;
+use key_converter::{gdk_button_to_key, gdk_scroll_to_key};
+use self::color::{ModeStyle, color_blue, color_orange, color_red, mode_styles};
 use self::dialog::DialogBuilder;
 use self::status_bar::StatusBar;
 use self::status_bar::Msg::{
@@ -97,14 +120,15 @@ use self::status_bar::Msg::{
     EntryShown,
     EntryText,
     Identifier,
+    Yanked,
 };
 use self::status_bar::ItemMsg::Text;
+use self::which_key::WhichKeyView;
 use self::Msg::*;
 pub use self::status_bar::StatusBarItem;
 use super::Modes;
 
-type Mappings = HashMap<&'static str, HashMap<Vec<Key>, String>>;
-type ModesHash = HashMap<&'static str, super::Mode>;
+type ModesHash = HashMap<String, ModeInfo>;
 type Variables = Vec<(&'static str, Box<dyn Fn() -> String>)>;
 
 /// A known mode or an unknown mode.
@@ -114,20 +138,22 @@ pub enum Mode {
     Command,
     Input,
     Normal,
+    Operator,
     Unknown,
 }
 
-/// A command from a map command.
-#[derive(Debug)]
-pub enum ShortcutCommand {
-    /// A complete command that is to be executed.
-    Complete(String),
-    /// An incomplete command where the user needs to complete it and press Enter.
-    Incomplete(String),
+/// Get the known mode corresponding to the mode name.
+fn known_mode(mode: &str) -> Mode {
+    match mode {
+        BLOCKING_INPUT_MODE => Mode::BlockingInput,
+        COMMAND_MODE => Mode::Command,
+        INPUT_MODE => Mode::Input,
+        NORMAL_MODE => Mode::Normal,
+        OPERATOR_MODE => Mode::Operator,
+        _ => Mode::Unknown,
+    }
 }
 
-const BLOCKING_INPUT_MODE: &str = "blocking-input";
-pub const COMMAND_MODE: &str = "command";
 const COMPLETE_NEXT_COMMAND: &str = "complete-next";
 const COMPLETE_PREVIOUS_COMMAND: &str = "complete-previous";
 const COPY: &str = "entry-copy";
@@ -136,16 +162,26 @@ const ENTRY_DELETE_NEXT_CHAR: &str = "entry-delete-next-char";
 const ENTRY_DELETE_NEXT_WORD: &str = "entry-delete-next-word";
 const ENTRY_DELETE_PREVIOUS_WORD: &str = "entry-delete-previous-word";
 const ENTRY_END: &str = "entry-end";
+const ENTRY_HISTORY_NEXT: &str = "entry-history-next";
+const ENTRY_HISTORY_PREVIOUS: &str = "entry-history-previous";
+const ENTRY_HISTORY_SEARCH: &str = "entry-history-search";
 const ENTRY_NEXT_CHAR: &str = "entry-next-char";
 const ENTRY_NEXT_WORD: &str = "entry-next-word";
 const ENTRY_PREVIOUS_CHAR: &str = "entry-previous-char";
 const ENTRY_PREVIOUS_WORD: &str = "entry-previous-word";
 const ENTRY_SMART_HOME: &str = "entry-smart-home";
+const HELP_PAGE_DOWN: &str = "help-page-down";
+const HELP_PAGE_UP: &str = "help-page-up";
 const INFO_MESSAGE_DURATION: u32 = 5000;
-const INPUT_MODE: &str = "input";
-const NORMAL_MODE: &str = "normal";
+const NORMAL_MODE_PREFIX: &str = "n";
+const OPERATOR_MODE_PREFIX: &str = "o";
 const PASTE: &str = "entry-paste";
+const PASTE_HISTORY: &str = "entry-paste-history";
 const PASTE_SELECTION: &str = "entry-paste-selection";
+const RECORD_MACRO: &str = "record-macro";
+const REPEAT_LAST: &str = "repeat-last";
+const REPLAY_MACRO: &str = "replay-macro";
+const SELECT_REGISTER: &str = "entry-select-register";
 
 #[derive(PartialEq)]
 pub enum ActivationType {
@@ -157,31 +193,76 @@ pub struct Model<COMM, SETT>
 where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
       SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
 {
+    aliases: HashMap<String, String>,
     answer: String, // TODO: is this field even used?
+    /// The prefixes of the modes added by the application, which are kept when reloading the config.
+    app_modes: Vec<String>,
     choices: Vec<char>,
+    /// The count typed before entering the command mode, given to the command typed in the entry.
+    command_count: Option<u32>,
+    /// Where the commands being executed come from, to remember where the mappings and settings come from.
+    command_source: MappingSource,
+    /// Whether the custom commands are emitted with their count, in `CustomCommandWithCount`.
+    commands_with_count: bool,
     completion_view: Component<CompletionView>,
     current_command_mode: char,
-    current_mode: Rc<Cell<Mode>>,
-    current_shortcut: Vec<Key>,
+    current_mode: Rc<CurrentMode>,
+    /// The current mode, the keys typed and the mappings, handled without GTK.
+    engine: Engine,
     entry_shown: bool,
+    /// The key press event sent to type a fed key, until it is handled.
+    fed_key_press: Option<EventKey>,
+    /// Whether the key press event being handled is the one sent to type a fed key.
+    fed_key_pressed: bool,
+    /// The key release event sent to type a fed key, until it is handled.
+    fed_key_release: Option<EventKey>,
     foreground_color: RGBA,
+    help_shown: bool,
+    help_view: Component<HelpView>,
+    history: History,
+    history_search: Option<HistorySearch>,
+    hooks: Vec<Hook>,
+    include_path: Option<PathBuf>,
     initial_errors: Vec<errors::Error>,
-    initial_parse_result: Option<ParseResult<COMM>>,
+    initial_parse_result: Option<ConfigResult<COMM>>,
     input_callback: Option<Box<dyn Fn(Option<String>, bool)>>,
-    mappings: Mappings,
+    key_policies: HashMap<String, KeyPolicy>,
+    last_command: Option<LastCommand<COMM>>,
+    last_replayed_register: Option<char>,
+    /// The number of keys of the mapping being executed.
+    mapping_keys_len: usize,
     message: String,
+    /// The CSS class of the current mode added to the status bar.
+    mode_css_class: Option<String>,
     mode_label: String,
-    mode_string: String,
+    mode_styles: HashMap<String, ModeStyle>,
     modes: ModesHash,
+    recording: Option<Recording>,
+    register_command: Option<RegisterCommand>,
+    registers: Registers,
     relm: Relm<Mg<COMM, SETT>>,
+    reloading_config: bool,
+    /// Whether the commands bound to an event are being executed.
+    running_hooks: bool,
+    /// The register used by the next copy, cut or paste in the command entry.
+    selected_register: Option<char>,
     settings: SETT,
+    /// The default values of the settings of mg, shown in the help.
+    setting_defaults: HashMap<String, Value>,
+    /// The values of the settings set by the commands, shown in the help.
+    setting_values: HashMap<String, Value>,
+    settings_filename: Option<PathBuf>,
     settings_parser: Box<Parser<COMM>>,
+    /// The canonical paths of the files being sourced, to avoid sourcing a file from itself.
+    sourced_files: Vec<PathBuf>,
     shortcuts: HashMap<Key, String>,
     shortcut_pressed: bool,
     show_count: bool,
     status_bar_command: String,
     status_bar_visible: bool,
+    user_modes: Modes,
     variables: HashMap<String, Box<dyn Fn() -> String>>,
+    which_key_view: Component<WhichKeyView>,
 }
 
 #[allow(missing_docs)]
@@ -190,41 +271,84 @@ pub enum Msg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
           SETT: Default + mg_settings::settings::Settings + EnumMetaData + SettingCompletion + 'static,
 {
+    /// Add a mode with its name and its prefix, and whether a count can be shown for it.
+    /// The mappings for this mode can be created as soon as it is added.
+    AddMode(String, String, bool),
     Alert(String),
     AppClose,
     BlockingCustomDialog(Box<dyn Responder>, DialogBuilder),
     BlockingInput(Box<dyn Responder>, String, String),
     BlockingQuestion(Box<dyn Responder>, String, Vec<char>),
     BlockingYesNoQuestion(Box<dyn Responder>, String),
+    ButtonPress(EventButton),
+    /// Run the hooks of the app-close event, then emit `AppClose`.
+    Close,
     CloseWin,
+    /// Emit the custom commands in `CustomCommandWithCount` instead of `CustomCommand`.
+    CommandsWithCount(bool),
     Completers(HashMap<&'static str, Box<dyn completion::Completer>>),
     CompletionViewChange(String),
     CustomCommand(COMM),
+    /// Emitted instead of `CustomCommand` after `CommandsWithCount(true)`, with the count typed before the command.
+    CustomCommandWithCount(COMM, Option<u32>),
     CustomDialog(DialogBuilder),
     DarkTheme(bool),
     DeleteCompletionItem,
     EnterCommandMode,
-    EnterNormalMode,
-    EnterNormalModeAndReset,
     Error(errors::Error),
+    /// Type the keys written in the mapping notation (i.e. `3gg:open foo<Enter>`), as if the user typed them.
+    FeedKeys(String),
+    FeedNextKey,
+    /// Ask for the mappings of all the modes, which are given back in the `Mappings` message.
+    GetMappings,
+    /// Ask for the content of a register, which is given back in the `Register` message.
+    GetRegister(char),
     HideColoredMessage(String),
     HideInfo(String),
+    HistoryFile(io::Result<PathBuf>),
     Info(String),
     InitAfter,
     Input(Box<dyn Responder>, String, String),
     KeyPress(EventKey),
     KeyRelease(EventKey),
+    /// The mappings of all the modes, sorted by mode and keys.
+    Mappings(Vec<MappingDescription>),
     Message(String),
     ModeChanged(String),
+    /// Set the motions which can follow an operator, as pairs of keys (i.e. "w") and motion names.
+    Motions(Vec<(&'static str, &'static str)>),
+    /// Emitted when an operator followed by a motion is typed.
+    OperatorCommand(Operation),
+    /// Set the operators, as pairs of keys (i.e. "d") and operator names.
+    Operators(Vec<(&'static str, &'static str)>),
+    /// Go back to the mode which was active before the current mode.
+    PopMode,
+    PopModeAndReset,
+    /// Enter a mode, and go back to the current mode when it is left.
+    PushMode(String),
     Question(Box<dyn Responder>, String, &'static [char]),
+    /// The content of a register (None if it is empty).
+    Register(char, Option<String>),
+    /// Emitted when the user changes the content of a register.
+    RegisterChanged(char, String),
+    RegistersFile(io::Result<PathBuf>),
     ResetInput,
-    SetMode(&'static str),
+    Scroll(EventScroll),
+    /// Enter a mode, forgetting the modes to go back to.
+    SetMode(String),
+    SetRegister(char, String),
     SetSetting(SETT::Variant),
     SettingChanged(SETT::Variant),
+    ShowMappings(String),
     StatusBarEntryActivate(String),
     StatusBarEntryChanged(String),
     StatusBarVisible(bool),
+    StatusBarYanked(Option<char>, String),
+    /// A timer started by the engine ran out.
+    Timeout(Timer),
     Title(String),
+    /// Type a key fed by the engine, as if the user typed it.
+    TypeKey(Key),
     Variables(Variables),
     Warning(String),
     YesNoQuestion(Box<dyn Responder>, String),
@@ -241,11 +365,15 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
         // NOTE: This code is not in init_view() because the SettingChanged signal would be sent
         // before the user's code connected to this event.
         let parse_result = self.model.initial_parse_result.take().expect("initial parse result");
-        self.execute_commands(parse_result, false);
+        self.update_command_names();
+        self.model.command_source = MappingSource::Config;
+        self.execute_commands(parse_result, false, None);
+        self.model.command_source = MappingSource::Runtime;
         let errors: Vec<_> = self.model.initial_errors.drain(..).collect();
         for error in errors {
             self.error(error);
         }
+        self.run_hooks(HookEvent::Startup);
     }
 
     /// Show an alert message to the user.
@@ -254,6 +382,39 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
         color_blue(self.status_bar.widget());
     }
 
+    /// Show the command entry to type a command.
+    fn enter_command_mode(&mut self) {
+        if self.model.status_bar_visible {
+            self.model.command_count = self.shortcut_prefix();
+            self.set_completer(DEFAULT_COMPLETER_IDENT);
+            self.set_current_identifier(':');
+            self.push_mode(COMMAND_MODE);
+            self.reset();
+            self.clear_shortcut();
+            self.model.history.reset_navigation();
+            self.model.completion_view.stream().emit(Visible(true));
+            self.show_entry();
+        }
+    }
+
+    /// Go back to the previous mode and hide the command entry.
+    fn pop_mode_and_reset(&mut self) {
+        self.pop_mode();
+        self.reset();
+        self.clear_shortcut();
+    }
+
+    /// Show the command entry to type a special command starting with the identifier (i.e. '/').
+    fn enter_special_command_mode(&mut self, identifier: char) {
+        self.model.command_count = self.shortcut_prefix();
+        self.set_completer(NO_COMPLETER_IDENT);
+        self.set_current_identifier(identifier);
+        self.push_mode(COMMAND_MODE);
+        self.reset();
+        self.clear_shortcut();
+        self.show_entry();
+    }
+
     /// Show an error to the user.
     fn error(&mut self, error: errors::Error) {
         let mut message = String::new();
@@ -309,13 +470,14 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
 
     /// Hide the command entry and the completion view.
     fn hide_entry_and_completion(&mut self) {
+        self.model.history_search = None;
         self.model.completion_view.stream().emit(Visible(false));
         self.model.entry_shown = false;
     }
 
     /// Check if the key should be inhibitted for a normal mode.
     #[allow(non_upper_case_globals)]
-    fn inhibit_normal_key_press(current_mode: &Rc<Cell<Mode>>, key: &EventKey) -> Inhibit {
+    fn inhibit_normal_key_press(current_mode: &Rc<CurrentMode>, key: &EventKey) -> Inhibit {
         match key.get_keyval() {
             colon | Escape => Inhibit(true),
             keyval => {
@@ -332,7 +494,10 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
 
     fn init_view(&mut self) {
         self.model.foreground_color = self.get_foreground_color();
+        self.show_mode_style();
         self.model.relm.stream().emit(InitAfter);
+        // Receive the mouse events which are not handled by the child widgets, to handle the mouse mappings.
+        self.window.add_events(EventMask::BUTTON_PRESS_MASK | EventMask::SCROLL_MASK);
 
         let completion_widget = self.model.completion_view.widget();
         self.set_completer(DEFAULT_COMPLETER_IDENT);
@@ -344,11 +509,25 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
         completion_widget.set_vexpand(true);
         self.overlay.add_overlay(completion_widget);
         completion_widget.show_all();
+
+        let help_widget = self.model.help_view.widget();
+        help_widget.set_hexpand(true);
+        help_widget.set_vexpand(true);
+        self.overlay.add_overlay(help_widget);
+        help_widget.show_all();
+        self.model.help_view.emit(help::Msg::Visible(false));
+
+        let which_key_widget = self.model.which_key_view.widget();
+        which_key_widget.set_hexpand(true);
+        which_key_widget.set_vexpand(true);
+        self.overlay.add_overlay(which_key_widget);
+        which_key_widget.show_all();
+        self.model.which_key_view.emit(which_key::Msg::Visible(false));
     }
 
     /// Input the specified command.
     fn input_command(&mut self, mut command: String) {
-        self.set_mode(COMMAND_MODE);
+        self.push_mode(COMMAND_MODE);
         self.show_entry();
         for (variable, function) in &self.model.variables {
             command = command.replace(&format!("<{}>", variable), &function());
@@ -366,94 +545,132 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
         if let Err(error) = create_default_config(default_config) {
             initial_errors.push(error.into());
         }
-        let (settings_parser, initial_parse_result, modes) =
+        let (settings_parser, initial_parse_result, modes, settings_filename) =
             match settings_filename {
                 Ok(settings_filename) => {
-                    let (parser, parse_result, modes) = parse_config(settings_filename, user_modes, include_path);
-                    (Box::new(parser), Some(parse_result), modes)
+                    let (parser, parse_result, modes) =
+                        parse_config(&settings_filename, user_modes, include_path.clone());
+                    (Box::new(parser), Some(parse_result), modes, Some(settings_filename))
                 },
                 Err(error) => {
                     initial_errors.push(error.into());
-                    (Box::new(Parser::<COMM>::new()), None, HashMap::new())
+                    (Box::new(Parser::<COMM>::new()), None, HashMap::new(), None)
                 },
             };
+        let key_policies = key_policies(&modes);
+        let mode_styles = mode_styles(&modes);
         Model {
+            aliases: HashMap::new(),
             answer: String::new(),
+            app_modes: vec![],
             choices: vec![],
+            command_count: None,
+            command_source: MappingSource::Runtime,
+            commands_with_count: false,
             completion_view: create_component::<CompletionView>(Self::default_completers()),
             current_command_mode: ':',
-            current_mode: Rc::new(Cell::new(Mode::Normal)),
-            current_shortcut: vec![],
+            current_mode: Rc::new(CurrentMode::new(Mode::Normal)),
+            engine: Engine::new(COMM::is_identifier),
             entry_shown: false,
+            fed_key_press: None,
+            fed_key_pressed: false,
+            fed_key_release: None,
             foreground_color: RGBA::white(),
+            help_shown: false,
+            help_view: create_component::<HelpView>(()),
+            history: History::new(),
+            history_search: None,
+            hooks: vec![],
+            include_path,
             initial_errors,
             initial_parse_result,
             input_callback: None,
-            mappings: HashMap::new(),
+            key_policies,
+            last_command: None,
+            last_replayed_register: None,
+            mapping_keys_len: 0,
             message: String::new(),
+            mode_css_class: None,
             mode_label: String::new(),
-            mode_string: NORMAL_MODE.to_string(),
+            mode_styles,
             modes,
+            recording: None,
+            register_command: None,
+            registers: Registers::new(),
             relm: relm.clone(),
+            reloading_config: false,
+            running_hooks: false,
+            selected_register: None,
             settings: SETT::default(),
+            setting_defaults: builtin_setting_defaults(),
+            setting_values: HashMap::new(),
+            settings_filename,
             settings_parser,
+            sourced_files: vec![],
             shortcuts: HashMap::new(),
             shortcut_pressed: false,
             show_count: true,
             status_bar_command: String::new(),
             status_bar_visible: true,
+            user_modes,
             variables: HashMap::new(),
+            which_key_view: create_component::<WhichKeyView>(()),
         }
     }
 
     /// Handle the key press event for the normal mode.
-    #[allow(non_upper_case_globals)]
+    /// The engine shows the command entry when `:` or the identifier of a special command is typed.
     fn normal_key_press(&mut self, key: &EventKey) -> Option<Msg<COMM, SETT>> {
-        match key.get_keyval() {
-            colon => Some(EnterCommandMode),
-            Escape => {
-                self.reset();
-                self.clear_shortcut();
-                self.handle_shortcut(key)
-            },
-            keyval => {
-                if let Some(character) = keyval.to_unicode() {
-                    if COMM::is_identifier(character) {
-                        self.set_completer(NO_COMPLETER_IDENT);
-                        self.set_current_identifier(character);
-                        self.set_mode(COMMAND_MODE);
-                        self.reset();
-                        self.clear_shortcut();
-                        self.show_entry();
-                        return None;
-                    }
-                }
-                self.handle_shortcut(key)
-            },
+        if key.get_keyval() == Escape {
+            self.reset();
+            self.hide_help();
         }
+        self.handle_shortcut(key);
+        None
     }
 
     /// Handle the escape event.
     fn reset(&mut self) {
+        self.reset_status_bar();
+        self.clear_shortcut();
+    }
+
+    /// Hide the command entry and the message, keeping the keys typed.
+    fn reset_status_bar(&mut self) {
         self.reset_colors();
         self.hide_entry_and_completion();
         self.model.message = String::new();
-        self.clear_shortcut();
     }
 
     /// Reset the input after closing a input dialog.
     fn reset_input(&mut self) {
         self.reset();
-        self.return_to_normal_mode();
+        self.pop_mode();
         self.model.choices.clear();
     }
 
-    fn return_to_normal_mode(&mut self) {
+    /// Go back to the mode which was active before the current mode (the normal mode if there is none) and hide
+    /// the command entry.
+    fn pop_mode(&mut self) {
         self.hide_entry_and_completion();
-        self.set_mode(NORMAL_MODE);
+        let previous_mode = self.model.engine.pop_mode();
+        self.update_mode(previous_mode);
         self.set_current_identifier(':');
     }
 
+    /// Enter the mode and remember the current mode to go back to it when this mode is left.
+    fn push_mode(&mut self, mode: &str) {
+        if let Some(previous_mode) = self.model.engine.push_mode(mode) {
+            self.update_mode(previous_mode);
+        }
+    }
+
+    /// Enter the mode and forget the modes to go back to.
+    fn replace_modes(&mut self, mode: &str) {
+        let previous_mode = self.model.engine.replace_modes(mode);
+        self.update_mode(previous_mode);
+    }
+
     /// Set the current (special) command identifier.
     fn set_current_identifier(&mut self, identifier: char) {
         self.model.current_command_mode = identifier;
@@ -463,30 +680,33 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
         self.model.status_bar_command = original_input.to_string();
     }
 
-    /// Set the current mode.
-    fn set_mode(&mut self, mode: &str) {
-        self.model.mode_string = mode.to_string();
-        let current_mode =
-            match mode {
-                BLOCKING_INPUT_MODE => Mode::BlockingInput,
-                COMMAND_MODE => Mode::Command,
-                INPUT_MODE => Mode::Input,
-                NORMAL_MODE => Mode::Normal,
-                _ => Mode::Unknown,
-            };
-        if current_mode == Mode::Unknown {
-            self.model.mode_label = mode.to_string();
+    /// Show the current mode, use its key handling and run the hooks of the modes left and entered.
+    fn update_mode(&mut self, previous_mode: String) {
+        let mode = self.model.engine.current_mode().to_string();
+        self.show_mode_style();
+        self.model.current_mode.set(known_mode(&mode));
+        self.update_key_handling();
+        self.model.relm.stream().emit(ModeChanged(mode.clone()));
+
+        for mode_info in self.model.modes.values() {
+            if mode_info.name == mode {
+                self.model.show_count = mode_info.show_count;
+            }
         }
-        else {
-            self.model.mode_label = String::new();
+
+        if previous_mode != mode {
+            self.run_hooks(HookEvent::ModeLeave(previous_mode));
+            self.run_hooks(HookEvent::ModeEnter(mode));
         }
-        self.model.current_mode.set(current_mode);
-        self.model.relm.stream().emit(ModeChanged(mode.to_string()));
+    }
 
-        for mode in self.model.modes.values() {
-            if mode.name == self.model.mode_string {
-                self.model.show_count = mode.show_count;
-            }
+    /// Show the label, the color and the CSS class of the current mode in the status bar.
+    /// The color is not shown over the color of a message.
+    fn show_mode_style(&mut self) {
+        self.model.mode_label = self.mode_label();
+        self.set_mode_css_class();
+        if self.model.message.is_empty() {
+            self.reset_colors();
         }
     }
 
@@ -496,6 +716,11 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
 
     fn update(&mut self, event: Msg<COMM, SETT>) {
         match event {
+            AddMode(name, prefix, show_count) => {
+                if self.add_mode(name, prefix.clone(), show_count) {
+                    self.model.app_modes.push(prefix);
+                }
+            },
             Alert(msg) => self.alert(&msg),
             // To be listened to by the user.
             AppClose => (),
@@ -505,33 +730,18 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
                 self.blocking_input(responder, question, default_answer),
             BlockingQuestion(responder, question, choices) => self.blocking_question(responder, question, choices),
             BlockingYesNoQuestion(responder, question) => self.blocking_yes_no_question(responder, question),
+            ButtonPress(event) => self.mouse_key_press(gdk_button_to_key(&event)),
+            Close => self.close(),
             CloseWin => unsafe { self.window.destroy() },
+            CommandsWithCount(with_count) => self.model.commands_with_count = with_count,
             Completers(completers) => self.model.completion_view.emit(AddCompleters(completers)),
             CompletionViewChange(completion) => self.set_input(&completion),
             // To be listened to by the user.
-            CustomCommand(_) => (),
+            CustomCommand(_) | CustomCommandWithCount(_, _) => (),
             CustomDialog(builder) => self.show_dialog(builder),
             DarkTheme(dark) => self.set_dark_theme(dark),
             DeleteCompletionItem => self.delete_current_completion_item(),
-            EnterCommandMode => {
-                if self.model.status_bar_visible {
-                    self.set_completer(DEFAULT_COMPLETER_IDENT);
-                    self.set_current_identifier(':');
-                    self.set_mode(COMMAND_MODE);
-                    self.reset();
-                    self.clear_shortcut();
-                    self.model.completion_view.stream().emit(Visible(true));
-                    self.show_entry();
-                }
-            },
-            EnterNormalMode => {
-                self.return_to_normal_mode();
-            },
-            EnterNormalModeAndReset => {
-                self.return_to_normal_mode();
-                self.reset();
-                self.clear_shortcut();
-            },
+            EnterCommandMode => self.enter_command_mode(),
             Info(msg) => self.info(&msg),
             InitAfter => self.after_children_added(),
             Input(responder, input, default_answer) => self.input(responder, input, default_answer),
@@ -539,20 +749,55 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
             KeyPress(key) => self.key_press(&key),
             KeyRelease(key) => self.key_release(&key),
             Error(error) => self.error(error),
+            FeedKeys(keys) => self.feed_typed_keys(&keys),
+            FeedNextKey => self.feed_next_key(),
+            GetMappings => {
+                let mappings = self.mapping_descriptions(None);
+                self.model.relm.stream().emit(Mappings(mappings));
+            },
+            GetRegister(register) => {
+                let content = self.model.registers.get(register).map(str::to_string);
+                self.model.relm.stream().emit(Register(register, content));
+            },
             HideColoredMessage(message) => self.hide_colored_message(&message),
             HideInfo(message) => self.hide_info(&message),
+            HistoryFile(filename) => self.set_history_file(filename),
             // To be listened by the user.
-            ModeChanged(_) | SettingChanged(_) => (),
+            Mappings(_) | ModeChanged(_) | OperatorCommand(_) | Register(_, _) | RegisterChanged(_, _) |
+                SettingChanged(_) => (),
+            Motions(motions) =>
+                match parse_key_names(motions) {
+                    Ok(motions) => self.model.engine.set_motions(motions),
+                    Err(error) => self.error(error),
+                },
+            Operators(operators) => self.set_operators(operators),
+            PopMode => self.pop_mode(),
+            PopModeAndReset => self.pop_mode_and_reset(),
+            PushMode(mode) => self.push_mode(&mode),
             Question(responder, question, choices) => self.question(responder, question, choices),
+            RegistersFile(filename) => self.set_registers_file(filename),
             ResetInput => self.reset_input(),
-            SetMode(mode) => self.set_mode(mode),
-            SetSetting(setting) => self.set_setting(setting),
+            Scroll(event) => self.mouse_key_press(gdk_scroll_to_key(&event)),
+            SetMode(mode) => self.replace_modes(&mode),
+            SetRegister(register, content) => self.set_register(register, content),
+            SetSetting(setting) => {
+                self.set_setting(setting);
+                self.run_any_setting_hooks();
+            },
+            ShowMappings(command) => self.show_mappings(command),
             StatusBarEntryActivate(input) => self.command_activate(input),
             StatusBarEntryChanged(input) => {
+                let searching = self.model.history_search.is_some();
+                if searching {
+                    self.update_history_search(&input);
+                }
                 // NOTE: Lock to prevent moving the cursor of the command entry.
                 let _lock = self.status_bar.stream().lock();
+                self.model.history.reset_navigation();
                 self.model.status_bar_command = input;
-                self.update_completions()
+                if !searching {
+                    self.update_completions()
+                }
             },
             StatusBarVisible(visible) => {
                 self.model.status_bar_visible = visible;
@@ -560,7 +805,13 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
                     self.model.completion_view.widget().set_visible(visible);
                 }
             },
+            StatusBarYanked(register, text) => self.yank(register, text),
+            Timeout(timer) => {
+                let actions = self.model.engine.timeout(timer);
+                self.execute_actions(actions);
+            },
             Title(title) => self.set_title(&title),
+            TypeKey(key) => self.type_fed_key(key),
             Variables(variables) => self.set_variables(variables),
             Warning(message) => self.warning(&message),
             YesNoQuestion(responder, question) => self.yes_no_question(responder, question),
@@ -602,12 +853,19 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
                             pack_type: PackType::Start,
                         },
                     },
+                    #[name="macro_recording"]
+                    StatusBarItem {
+                        child: {
+                            pack_type: PackType::Start,
+                        },
+                    },
                     #[name="shortcut"]
                     StatusBarItem {
-                        Text: shortcut_to_string(&self.model.current_shortcut, self.model.show_count),
+                        Text: shortcut_to_string(&self.model.engine.shown_keys(), self.model.show_count),
                     },
                     EntryActivate(ref input) => StatusBarEntryActivate(input.clone()),
                     EntryChanged(ref text) => StatusBarEntryChanged(text.clone()),
+                    Yanked(register, ref text) => StatusBarYanked(register, text.clone()),
                 },
                 #[name="overlay"]
                 gtk::Overlay {
@@ -620,7 +878,11 @@ impl<COMM, SETT> Widget for Mg<COMM, SETT>
             key_press_event(_, key) with(current_mode) =>
                 (KeyPress(key.clone()), Self::inhibit_key_press(&current_mode, key)),
             key_release_event(_, key) => (KeyRelease(key.clone()), Inhibit(false)),
-            delete_event(_, _) => (AppClose, Inhibit(true)),
+            button_press_event(_, event) with(current_mode) =>
+                (ButtonPress(event.clone()), Self::inhibit_mouse_key(&current_mode, gdk_button_to_key(event))),
+            scroll_event(_, event) with(current_mode) =>
+                (Scroll(event.clone()), Self::inhibit_mouse_key(&current_mode, gdk_scroll_to_key(event))),
+            delete_event(_, _) => (Close, Inhibit(true)),
         },
     }
 }
@@ -629,6 +891,13 @@ impl<COMM, SETT> Mg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
           SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
 {
+    /// Run the hooks of the app-close event before the application listening to `AppClose` quits, so that the
+    /// messages emitted by these hooks are sent first.
+    fn close(&mut self) {
+        self.run_hooks(HookEvent::AppClose);
+        self.model.relm.stream().emit(AppClose);
+    }
+
     fn is_normal_command(&self) -> bool {
         self.model.current_command_mode == ':'
     }
@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! The operators and the motions given by the application, composed by the engine in the operator-pending mode
+//! (i.e. `2d3w`).
+
+use std::collections::HashMap;
+
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+use mg_settings::errors::{Error, Result};
+
+use app::Mg;
+use engine::OPERATOR_MODE;
+use key::Key;
+use key_parser::parse_keys;
+use super::OPERATOR_MODE_PREFIX;
+
+/// Parse the keys of the operators or of the motions.
+pub fn parse_key_names(names: Vec<(&'static str, &'static str)>) -> Result<HashMap<Vec<Key>, String>> {
+    names.into_iter()
+        .map(|(keys, name)| parse_keys(keys).map(|keys| (keys, name.to_string())))
+        .collect()
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Set the operators, which need the operator-pending mode.
+    /// This mode does not exist when a mode of the application uses its prefix.
+    pub fn set_operators(&mut self, operators: Vec<(&'static str, &'static str)>) {
+        if !self.model.modes.values().any(|mode| mode.name == OPERATOR_MODE) {
+            self.error(Error::Msg(format!("Cannot use operators: the prefix {} of the {} mode is used by another mode",
+                OPERATOR_MODE_PREFIX, OPERATOR_MODE)));
+            return;
+        }
+        match parse_key_names(operators) {
+            Ok(operators) => self.model.engine.set_operators(operators),
+            Err(error) => self.error(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use key::Key::{Char, Control};
+    use super::parse_key_names;
+
+    #[test]
+    fn parse_names() {
+        let names = parse_key_names(vec![("d", "delete"), ("<C-w>", "window")]).unwrap();
+        assert_eq!(names.get(&vec![Char('d')]).map(String::as_str), Some("delete"));
+        assert_eq!(names.get(&vec![Control(Box::new(Char('w')))]).map(String::as_str), Some("window"));
+        assert!(parse_key_names(vec![("<Foo>", "foo")]).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Named registers, holding the keyboard macros and the text copied from the command entry.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
+use mg_settings::errors::Error;
+
+use app::Mg;
+use app::Msg::RegisterChanged;
+use app::status_bar::ItemMsg::Text;
+use app::status_bar::Msg::{InsertText, Paste};
+use key::Key::{self, Char, Escape};
+use key_parser::{keys_to_string, parse_keys};
+use self::RegisterCommand::{Record, Replay, Select};
+
+/// The register name meaning the last replayed register.
+const LAST_REPLAYED_REGISTER: char = '@';
+/// The number of texts copied or cut from the command entry which are remembered.
+const YANK_HISTORY_SIZE: usize = 10;
+
+/// A command waiting for the user to type the name of a register.
+#[derive(Clone, Copy)]
+pub enum RegisterCommand {
+    /// Start recording a macro into the register.
+    Record,
+    /// Replay the macro in the register, the specified number of times.
+    Replay(Option<u32>),
+    /// Use the register for the next copy, cut or paste in the command entry.
+    Select,
+}
+
+/// A macro being recorded.
+pub struct Recording {
+    keys: Vec<Key>,
+    register: char,
+}
+
+/// The named registers, saved in a file, and the yank history, which is not saved.
+/// A macro is stored as its keys written in the mapping notation.
+pub struct Registers {
+    filename: Option<PathBuf>,
+    history: VecDeque<String>,
+    registers: HashMap<char, String>,
+}
+
+impl Registers {
+    /// Create empty registers which are not saved.
+    #[allow(unknown_lints, new_without_default_derive)]
+    pub fn new() -> Self {
+        Registers {
+            filename: None,
+            history: VecDeque::new(),
+            registers: HashMap::new(),
+        }
+    }
+
+    /// Add a text copied or cut from the command entry to the yank history.
+    pub fn add_to_history(&mut self, text: String) {
+        self.history.push_front(text);
+        self.history.truncate(YANK_HISTORY_SIZE);
+    }
+
+    /// Get the content of a register.
+    pub fn get(&self, register: char) -> Option<&str> {
+        self.registers.get(&register).map(String::as_str)
+    }
+
+    /// Get a text of the yank history, starting at 1 for the last copied text.
+    pub fn history_entry(&self, index: usize) -> Option<&str> {
+        index.checked_sub(1)
+            .and_then(|index| self.history.get(index))
+            .map(String::as_str)
+    }
+
+    /// Load the registers from the file and save the registers in this file from now on.
+    pub fn load(&mut self, filename: PathBuf) -> io::Result<()> {
+        self.registers.clear();
+        if filename.exists() {
+            let file = File::open(&filename)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let mut chars = line.chars();
+                if let Some(register) = chars.next() {
+                    self.registers.insert(register, unescape(chars.as_str()));
+                }
+            }
+        }
+        self.filename = Some(filename);
+        Ok(())
+    }
+
+    /// Set the content of a register and save the registers.
+    pub fn set(&mut self, register: char, content: String) -> io::Result<()> {
+        self.registers.insert(register, content);
+        self.save()
+    }
+
+    /// Save the registers to their file, if any.
+    /// Each register is saved on its own line, so the line breaks of its content are escaped.
+    fn save(&self) -> io::Result<()> {
+        if let Some(ref filename) = self.filename {
+            let mut file = File::create(filename)?;
+            for (register, content) in &self.registers {
+                writeln!(file, "{}{}", register, escape(content))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escape the backslashes and the line breaks of the content of a register.
+fn escape(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    for character in content.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Get back the content of a register escaped by `escape`.
+fn unescape(line: &str) -> String {
+    let mut content = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(character) = chars.next() {
+        if character == '\\' {
+            match chars.next() {
+                Some('n') => content.push('\n'),
+                Some('r') => content.push('\r'),
+                Some(character) => content.push(character),
+                None => content.push('\\'),
+            }
+        }
+        else {
+            content.push(character);
+        }
+    }
+    content
+}
+
+/// Check if the character can be used as a register name.
+pub fn is_register_name(character: char) -> bool {
+    character.is_ascii_alphanumeric()
+}
+
+impl<COMM, SETT> Mg<COMM, SETT>
+    where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
+          SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
+{
+    /// Use the key as the register name of the command waiting for one.
+    /// Returns true if the key was used.
+    pub fn handle_register_name(&mut self, key: &Key) -> bool {
+        if let Some(command) = self.model.register_command.take() {
+            self.model.current_mode.set_waiting_for_register(false);
+            match (key, command) {
+                (&Escape, _) => (),
+                (&Char(register), Record) if is_register_name(register) => self.start_macro_recording(register),
+                (&Char(register), Replay(count)) if is_register_name(register) || register == LAST_REPLAYED_REGISTER =>
+                    self.replay_macro(register, count),
+                (&Char(register), Select) if is_register_name(register) =>
+                    self.model.selected_register = Some(register),
+                _ => self.error(Error::Msg(format!("Invalid register: {}", keys_to_string(&[key.clone()])))),
+            }
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Paste the text of the yank history at the index (1 for the last copied text).
+    pub fn paste_history(&mut self, count: Option<u32>) {
+        let index = count.unwrap_or(1);
+        let text = self.model.registers.history_entry(index as usize).map(str::to_string);
+        match text {
+            Some(text) => self.status_bar.emit(InsertText(text)),
+            None => self.error(Error::Msg(format!("No text at index {} of the yank history", index))),
+        }
+    }
+
+    /// Paste the selected register, or the clipboard if there is no selected register.
+    pub fn paste_register(&mut self) {
+        if let Some(register) = self.model.selected_register {
+            let text = self.model.registers.get(register).map(str::to_string);
+            match text {
+                Some(text) => self.status_bar.emit(InsertText(text)),
+                None => self.error(Error::Msg(format!("Register {} is empty", register))),
+            }
+        }
+        else {
+            self.status_bar.emit(Paste);
+        }
+    }
+
+    /// Add a key typed by the user to the macro being recorded.
+    pub fn record_key(&mut self, key: &Key) {
+        if let Some(ref mut recording) = self.model.recording {
+            recording.keys.push(key.clone());
+        }
+    }
+
+    /// Stop recording the macro or wait for the name of the register to record into.
+    pub fn record_macro(&mut self) {
+        if let Some(mut recording) = self.model.recording.take() {
+            // Do not record the keys of the mapping which stopped the recording.
+            let len = recording.keys.len().saturating_sub(self.model.mapping_keys_len);
+            recording.keys.truncate(len);
+            self.macro_recording.emit(Text(String::new()));
+            let content = keys_to_string(&recording.keys);
+            self.save_register(recording.register, content);
+        }
+        else {
+            self.wait_for_register(Record);
+        }
+    }
+
+    /// Feed the keys of the macro in the register.
+    fn replay_macro(&mut self, register: char, count: Option<u32>) {
+        let register =
+            if register == LAST_REPLAYED_REGISTER {
+                match self.model.last_replayed_register {
+                    Some(register) => register,
+                    None => {
+                        self.error(Error::Msg("No previously replayed register".to_string()));
+                        return;
+                    },
+                }
+            }
+            else {
+                register
+            };
+        let result = self.model.registers.get(register)
+            .ok_or_else(|| Error::Msg(format!("Register {} is empty", register)))
+            .and_then(parse_keys);
+        let keys =
+            match result {
+                Ok(keys) => keys,
+                Err(error) => {
+                    self.error(error);
+                    return;
+                },
+            };
+        self.model.last_replayed_register = Some(register);
+        self.feed_keys(keys, count.unwrap_or(1), true);
+    }
+
+    /// Wait for the name of the register to replay, the specified number of times.
+    pub fn replay_macro_command(&mut self, count: Option<u32>) {
+        self.wait_for_register(Replay(count));
+    }
+
+    /// Set the content of the register and tell the application that the user changed it.
+    fn save_register(&mut self, register: char, content: String) {
+        if let Err(error) = self.model.registers.set(register, content.clone()) {
+            self.error(error.into());
+        }
+        self.model.relm.stream().emit(RegisterChanged(register, content));
+    }
+
+    /// Wait for the name of the register to use for the next copy, cut or paste in the command entry.
+    pub fn select_register(&mut self) {
+        self.wait_for_register(Select);
+    }
+
+    /// Set the content of the register, as requested by the application.
+    pub fn set_register(&mut self, register: char, content: String) {
+        if let Err(error) = self.model.registers.set(register, content) {
+            self.error(error.into());
+        }
+    }
+
+    /// Load the registers from the file and use this file to save the registers.
+    pub fn set_registers_file(&mut self, filename: io::Result<PathBuf>) {
+        let result = filename.and_then(|filename| self.model.registers.load(filename));
+        if let Err(error) = result {
+            self.error(error.into());
+        }
+    }
+
+    /// Start recording the keys typed by the user into the register.
+    fn start_macro_recording(&mut self, register: char) {
+        self.model.recording = Some(Recording {
+            keys: vec![],
+            register,
+        });
+        self.macro_recording.emit(Text(format!("recording @{}", register)));
+    }
+
+    /// Wait for the user to type the name of the register for the command.
+    fn wait_for_register(&mut self, command: RegisterCommand) {
+        self.model.register_command = Some(command);
+        self.model.current_mode.set_waiting_for_register(true);
+    }
+
+    /// Add the text copied or cut from the command entry to the yank history and to the register, if any.
+    pub fn yank(&mut self, register: Option<char>, text: String) {
+        if let Some(register) = register {
+            self.save_register(register, text.clone());
+        }
+        self.model.registers.add_to_history(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{Registers, YANK_HISTORY_SIZE, escape, is_register_name, unescape};
+
+    #[test]
+    fn escape_content() {
+        for content in &["", "gg", "first line\nsecond line\n", "C:\\new\\", "\\n\r\n", "trailing \\"] {
+            assert_eq!(unescape(&escape(content)), *content);
+            assert!(!escape(content).contains('\n'));
+        }
+    }
+
+    #[test]
+    fn save_multi_line_registers() {
+        let filename = env::temp_dir().join("mg-test-save-multi-line-registers");
+        let _ = fs::remove_file(&filename);
+        let mut registers = Registers::new();
+        registers.load(filename.clone()).unwrap();
+        registers.set('a', "first\nsecond\\n".to_string()).unwrap();
+        registers.set('b', "\nb".to_string()).unwrap();
+        let mut loaded_registers = Registers::new();
+        loaded_registers.load(filename.clone()).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(loaded_registers.get('a'), Some("first\nsecond\\n"));
+        assert_eq!(loaded_registers.get('b'), Some("\nb"));
+        assert_eq!(loaded_registers.registers.len(), 2);
+    }
+
+    #[test]
+    fn register_names() {
+        assert!(is_register_name('a'));
+        assert!(is_register_name('Z'));
+        assert!(is_register_name('0'));
+        assert!(!is_register_name('"'));
+        assert!(!is_register_name(' '));
+        assert!(!is_register_name('é'));
+    }
+
+    #[test]
+    fn yank_history() {
+        let mut registers = Registers::new();
+        assert_eq!(registers.history_entry(1), None);
+        registers.add_to_history("foo".to_string());
+        registers.add_to_history("bar".to_string());
+        assert_eq!(registers.history_entry(0), None);
+        assert_eq!(registers.history_entry(1), Some("bar"));
+        assert_eq!(registers.history_entry(2), Some("foo"));
+        assert_eq!(registers.history_entry(3), None);
+        for index in 0..YANK_HISTORY_SIZE {
+            registers.add_to_history(index.to_string());
+        }
+        assert_eq!(registers.history_entry(YANK_HISTORY_SIZE), Some("0"));
+        assert_eq!(registers.history_entry(YANK_HISTORY_SIZE + 1), None);
+    }
+
+    #[test]
+    fn set_registers() {
+        let mut registers = Registers::new();
+        assert_eq!(registers.get('a'), None);
+        registers.set('a', "gg".to_string()).unwrap();
+        registers.set('a', "G".to_string()).unwrap();
+        assert_eq!(registers.get('a'), Some("G"));
+    }
+}
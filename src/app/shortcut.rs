@@ -19,25 +19,22 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use std::cell::Cell;
 use std::rc::Rc;
 
 use gdk::{EventKey, ModifierType};
-use gdk::keys::constants::{Escape, Tab, ISO_Left_Tab};
+use gdk::keys::constants::{Down, Escape, Tab, ISO_Left_Tab, Up};
 use gtk::{Inhibit, LabelExt};
 use mg_settings::{self, EnumFromStr, EnumMetaData, SettingCompletion, SpecialCommand};
-use mg_settings::key::Key::{self, Char};
+use mg_settings::errors::Error;
+use relm::timeout;
 
-use app::{
-    Mg,
-    Mode,
-    Msg,
-    BLOCKING_INPUT_MODE,
-    COMMAND_MODE,
-    INPUT_MODE,
-};
-use app::ShortcutCommand::{Complete, Incomplete};
-use key_converter::gdk_key_to_key;
+use app::{Mg, Mode};
+use app::key_policy::CurrentMode;
+use app::Msg::{EnterCommandMode, OperatorCommand, Timeout, TypeKey};
+use app::which_key::Msg::Continuations;
+use engine::Action;
+use key::Key;
+use key_converter::{gdk_key_to_key, gdk_keycode_to_key};
 
 /// Convert a shortcut of keys to a `String`.
 pub fn shortcut_to_string(keys: &[Key], show_count: bool) -> String {
@@ -54,35 +51,72 @@ impl<COMM, SETT> Mg<COMM, SETT>
     where COMM: Clone + EnumFromStr + EnumMetaData + SpecialCommand + 'static,
           SETT: Default + EnumMetaData + mg_settings::settings::Settings + SettingCompletion + 'static,
 {
-    /// Add the key to the current shortcut.
-    pub fn add_to_shortcut(&mut self, key: Key) {
-        self.model.current_shortcut.push(key);
-        self.update_shortcut_label();
-    }
-
     /// Clear the current shortcut buffer.
     pub fn clear_shortcut(&mut self) {
-        self.model.current_shortcut.clear();
+        let actions = self.model.engine.clear_shortcut();
+        self.execute_actions(actions);
+    }
+
+    /// Do what the engine asks after it handled a key or a timer.
+    pub fn execute_actions(&mut self, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Command { command, count, keys_len } => {
+                    self.model.mapping_keys_len = keys_len;
+                    let msg = self.handle_command(command, false, count);
+                    self.model.mapping_keys_len = 0;
+                    if let Some(msg) = msg {
+                        self.model.relm.stream().emit(msg);
+                    }
+                },
+                Action::CompleteCommand(command, count) => {
+                    self.input_command(command);
+                    self.model.command_count = count;
+                    self.show_completion();
+                },
+                Action::EnterCommandMode => self.model.relm.stream().emit(EnterCommandMode),
+                Action::EnterSpecialCommandMode(identifier) => self.enter_special_command_mode(identifier),
+                Action::Error(message) => self.error(Error::Msg(message)),
+                Action::ModeChanged(previous_mode) => self.update_mode(previous_mode),
+                Action::Operation(operation) => self.model.relm.stream().emit(OperatorCommand(operation)),
+                Action::Reset => {
+                    // The command entry stays shown when the key is typed in it.
+                    if !self.model.entry_shown {
+                        self.reset_status_bar();
+                    }
+                },
+                Action::StartTimer(timer, delay) => timeout(self.model.relm.stream(), delay, move || Timeout(timer)),
+                Action::TypeKey(key) => self.model.relm.stream().emit(TypeKey(key)),
+                Action::WhichKey(continuations) => self.model.which_key_view.emit(Continuations(continuations)),
+            }
+        }
+        self.model.current_mode.set_shortcut_pending(!self.model.engine.shortcut().is_empty());
         self.update_shortcut_label();
     }
 
     /// Handle a shortcut in input mode.
     pub fn handle_input_shortcut(&mut self, key: &EventKey) -> bool {
-        if let Some(key) = gdk_key_to_key(key) {
-            if self.model.shortcuts.contains_key(&key) {
-                let answer = &self.model.shortcuts[&key].clone();
-                self.model.shortcut_pressed = true;
-                // set_dialog_answer() must be called after setting shortcut_pressed because this
-                // method will set the answer to a Shortcut in this case.
-                self.set_dialog_answer(answer);
-                return true;
-            }
+        match gdk_key_to_key(key) {
+            Some(key) => self.handle_input_shortcut_key(&key),
+            None => false,
+        }
+    }
+
+    /// Handle a key of a shortcut in input mode.
+    pub fn handle_input_shortcut_key(&mut self, key: &Key) -> bool {
+        if self.model.shortcuts.contains_key(key) {
+            let answer = &self.model.shortcuts[key].clone();
+            self.model.shortcut_pressed = true;
+            // set_dialog_answer() must be called after setting shortcut_pressed because this
+            // method will set the answer to a Shortcut in this case.
+            self.set_dialog_answer(answer);
+            return true;
         }
         false
     }
 
     /// Check if the key should be inhibitted for the shortcut.
-    pub fn inhibit_handle_shortcut(current_mode: &Rc<Cell<Mode>>, key: &EventKey) -> Inhibit {
+    pub fn inhibit_handle_shortcut(current_mode: &Rc<CurrentMode>, key: &EventKey) -> Inhibit {
         let keyval = key.get_keyval();
         let alt_pressed = key.get_state().contains(ModifierType::MOD1_MASK);
         let control_pressed = key.get_state().contains(ModifierType::CONTROL_MASK);
@@ -93,125 +127,46 @@ impl<COMM, SETT> Mg<COMM, SETT>
             current_mode == Mode::Normal || keyval == Escape ||
                 ((current_mode == Mode::Command || current_mode == Mode::Input || current_mode == Mode::BlockingInput) &&
                  (alt_pressed || control_pressed || (!is_char && shift_pressed) || keyval == Tab ||
-                  keyval == ISO_Left_Tab));
+                  keyval == ISO_Left_Tab || keyval == Up || keyval == Down));
         Inhibit(should_inhibit)
     }
 
     /// Handle a possible input of a shortcut.
-    pub fn handle_shortcut(&mut self, key: &EventKey) -> Option<Msg<COMM, SETT>> {
+    pub fn handle_shortcut(&mut self, key: &EventKey) {
         let keyval = key.get_keyval();
         let alt_pressed = key.get_state().contains(ModifierType::MOD1_MASK);
         let control_pressed = key.get_state().contains(ModifierType::CONTROL_MASK);
         let shift_pressed = key.get_state().contains(ModifierType::SHIFT_MASK);
         if !self.model.entry_shown || alt_pressed || control_pressed || shift_pressed || keyval == Tab ||
-            keyval == ISO_Left_Tab
+            keyval == ISO_Left_Tab || keyval == Up || keyval == Down
         {
-            if let Some(key) = gdk_key_to_key(key) {
-                self.add_to_shortcut(key);
-                let action = {
-                    let mut current_mode = self.model.mode_string.clone();
-                    // The input modes have the same mappings as the command mode.
-                    if current_mode == INPUT_MODE || current_mode == BLOCKING_INPUT_MODE {
-                        current_mode = COMMAND_MODE.to_string();
-                    }
-                    self.model.mappings.get(&current_mode.as_ref())
-                        .and_then(|mappings| mappings.get(self.shortcut_without_prefix()).cloned())
-                };
-                if let Some(action) = action {
-                    let prefix = self.shortcut_prefix();
-                    // FIXME: this is copied a couple of lines below.
-                    if !self.model.entry_shown {
-                        // TODO: document why we need this.
-                        self.reset();
-                    }
-                    self.clear_shortcut();
-                    match self.action_to_command(&action) {
-                        Complete(command) => {
-                            return self.handle_command(command, false, prefix);
-                        },
-                        Incomplete(command) => {
-                            self.input_command(command);
-                            self.show_completion();
-                        },
-                    }
-                }
-                else if self.no_possible_shortcut() {
-                    let current_mode = self.model.current_mode.get();
-                    if current_mode != Mode::Input && !self.model.entry_shown {
-                        // TODO: document why we need this.
-                        self.reset();
-                    }
-                    self.clear_shortcut();
+            if self.model.fed_key_pressed {
+                if let Some(key) = gdk_key_to_key(key) {
+                    self.handle_fed_key(key);
                 }
             }
-        }
-        None
-    }
-
-    /// Check if there are no possible shortcuts.
-    fn no_possible_shortcut(&self) -> bool {
-        if let Some(mappings) = self.model.mappings.get(&self.model.mode_string.as_ref()) {
-            let shortcut = self.shortcut_without_prefix();
-            for key in mappings.keys() {
-                if key.starts_with(shortcut) {
-                    return false;
-                }
+            else if let Some(key) = self.model.engine.shortcut_key(gdk_key_to_key(key), gdk_keycode_to_key(key)) {
+                self.handle_shortcut_key(key);
             }
         }
-        true
     }
 
-    fn shortcut_prefix(&self) -> Option<u32> {
-        let mut digits = self.model.current_shortcut.iter()
-            .take_while(is_digit)
-            .peekable();
-        if digits.peek().is_none() {
-            None
-        }
-        else {
-            let num = digits
-                .fold(0, |num, key| {
-                    if let Char(c) = *key {
-                        if let Some(digit) = c.to_digit(10) {
-                            return num * 10 + digit;
-                        }
-                    }
-                    num
-                });
-            Some(num)
-        }
+    /// Give the key typed by the user to the engine.
+    pub fn handle_shortcut_key(&mut self, key: Key) {
+        let actions = self.model.engine.key(key);
+        self.execute_actions(actions);
     }
 
-    fn shortcut_without_prefix(&self) -> &[Key] {
-        let first = self.model.current_shortcut.first().cloned().unwrap_or(Char('0'));
-        let start =
-            if first == Char('0') {
-                0
-            }
-            else {
-                self.model.current_shortcut.iter()
-                    .position(is_not_digit)
-                    .unwrap_or_else(|| self.model.current_shortcut.len())
-            };
-        &self.model.current_shortcut[start..]
+    /// Get the count typed before the current shortcut.
+    pub fn shortcut_prefix(&self) -> Option<u32> {
+        self.model.engine.shortcut().count()
     }
 
     // TODO: remove this when updating the model in methods outside the trait will update the view.
     /// Update the shortcut label.
-    fn update_shortcut_label(&self) {
-        self.shortcut.widget().set_text(&shortcut_to_string(&self.model.current_shortcut, self.model.show_count));
-    }
-}
-
-fn is_digit(key: &&Key) -> bool {
-    !is_not_digit(key)
-}
-
-fn is_not_digit(key: &Key) -> bool {
-    if let Char(c) = *key {
-        !c.to_digit(10).is_some()
-    }
-    else {
-        true
+    /// In the operator-pending mode, the label shows the operator and the keys typed after it.
+    pub fn update_shortcut_label(&self) {
+        let text = shortcut_to_string(&self.model.engine.shown_keys(), self.model.show_count);
+        self.shortcut.widget().set_text(&text);
     }
 }
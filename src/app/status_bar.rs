@@ -48,8 +48,10 @@ use self::ItemMsg::{Color, Text};
 #[derive(Msg)]
 pub enum Msg {
     BarVisible(bool),
-    Copy,
-    Cut,
+    /// Copy the selection to the register, or to the clipboard if there is no register.
+    Copy(Option<char>),
+    /// Cut the selection to the register, or to the clipboard if there is no register.
+    Cut(Option<char>),
     DeleteNextChar,
     DeleteNextWord,
     DeletePreviousWord,
@@ -59,6 +61,7 @@ pub enum Msg {
     EntryText(String),
     EntryShown(bool),
     Identifier(String),
+    InsertText(String),
     NextChar,
     NextWord,
     Paste,
@@ -67,6 +70,8 @@ pub enum Msg {
     PreviousWord,
     ShowIdentifier,
     SmartHome,
+    /// Emitted with the text copied or cut, and the register it was copied to.
+    Yanked(Option<char>, String),
 }
 
 pub struct Model {
@@ -118,8 +123,8 @@ impl Widget for StatusBar {
     fn update(&mut self, msg: Msg) {
         match msg {
             BarVisible(visible) => self.model.visible = visible,
-            Copy => self.copy(),
-            Cut => self.cut(),
+            Copy(register) => self.copy(register),
+            Cut(register) => self.cut(register),
             DeleteNextChar => self.delete_next_char(),
             DeleteNextWord => self.delete_next_word(),
             DeletePreviousWord => self.delete_previous_word(),
@@ -128,6 +133,7 @@ impl Widget for StatusBar {
             EntryShown(visible) => self.set_entry_shown(visible),
             EntryText(input) => self.set_input(&input),
             Identifier(identifier) => self.set_identifier(&identifier),
+            InsertText(text) => self.insert_text(&text),
             NextChar => self.next_char(),
             NextWord => self.next_word(),
             Paste => self.paste(),
@@ -136,6 +142,7 @@ impl Widget for StatusBar {
             PreviousWord => self.previous_word(),
             ShowIdentifier => self.show_identifier(),
             SmartHome => self.smart_home(),
+            Yanked(_, _) => (), // NOTE: to be listened by the user.
         }
     }
 
@@ -163,14 +170,23 @@ impl Widget for StatusBar {
 }
 
 impl StatusBar {
-    /// Copy the selection to the clipboard.
-    fn copy(&self) {
-        self.command_entry.copy_clipboard();
+    /// Copy the selection to the register, or to the clipboard if there is no register.
+    fn copy(&self, register: Option<char>) {
+        if register.is_none() {
+            self.command_entry.copy_clipboard();
+        }
+        self.emit_yanked(register);
     }
 
-    /// Cut the selection to the clipboard.
-    fn cut(&self) {
-        self.command_entry.cut_clipboard();
+    /// Cut the selection to the register, or to the clipboard if there is no register.
+    fn cut(&self, register: Option<char>) {
+        self.emit_yanked(register);
+        if register.is_none() {
+            self.command_entry.cut_clipboard();
+        }
+        else if self.delete_selection() {
+            self.emit_entry_changed();
+        }
     }
 
     /// Delete the character after the cursor.
@@ -251,6 +267,15 @@ impl StatusBar {
         self.model.relm.stream().emit(EntryChanged(self.command_entry.get_text().to_string()));
     }
 
+    /// Emit the Yanked event with the selected text, if any.
+    fn emit_yanked(&self, register: Option<char>) {
+        if let Some((start, end)) = self.command_entry.get_selection_bounds() {
+            if let Some(text) = self.command_entry.get_chars(start, end) {
+                self.model.relm.stream().emit(Yanked(register, text.to_string()));
+            }
+        }
+    }
+
     /// Go to the end of the command entry.
     fn end(&self) {
         let text = self.get_command();
@@ -262,6 +287,14 @@ impl StatusBar {
         self.command_entry.get_text().to_string()
     }
 
+    /// Insert the text to the selection or the cursor position.
+    fn insert_text(&self, text: &str) {
+        self.delete_selection();
+        let mut position = self.command_entry.get_position();
+        self.command_entry.insert_text(text, &mut position);
+        self.command_entry.set_position(position);
+    }
+
     /// Go forward one character in the command entry.
     fn next_char(&self) {
         let pos = self.command_entry.get_position();
@@ -292,12 +325,9 @@ impl StatusBar {
 
     /// Paste the selection clipboard to the selection or the cursor position.
     fn paste_selection(&self) {
-        self.delete_selection();
         let clipboard = Clipboard::get(&SELECTION_PRIMARY);
         if let Some(text) = clipboard.wait_for_text() {
-            let mut position = self.command_entry.get_position();
-            self.command_entry.insert_text(&text, &mut position);
-            self.command_entry.set_position(position);
+            self.insert_text(&text);
         }
     }
 
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! A popup listing the mappings which continue the keys typed so far.
+
+use glib::ToValue;
+use glib::types::Type;
+use gtk;
+use gtk::{
+    prelude::GtkListStoreExtManual,
+    Align,
+    CellLayoutExt,
+    CellRendererText,
+    GtkListStoreExt,
+    ListStore,
+    ScrolledWindowExt,
+    TreeViewColumn,
+    TreeViewColumnExt,
+    TreeViewExt,
+    WidgetExt,
+};
+use gtk::PolicyType::{Automatic, Never};
+use relm::Widget;
+use relm_derive::widget;
+
+use self::Msg::*;
+
+const WHICH_KEY_VIEW_MAX_HEIGHT: i32 = 300;
+
+#[allow(missing_docs)]
+pub struct Model {
+    visible: bool,
+}
+
+/// The keys to type after the pending keys and the action of the mapping.
+pub type Continuation = (String, String);
+
+#[derive(Msg)]
+pub enum Msg {
+    Continuations(Vec<Continuation>),
+    Visible(bool),
+}
+
+/// A widget showing the possible continuations of a pending shortcut.
+#[widget]
+impl Widget for WhichKeyView {
+    fn init_view(&mut self) {
+        for index in 0..2 {
+            let view_column = TreeViewColumn::new();
+            let cell = CellRendererText::new();
+            view_column.pack_start(&cell, true);
+            view_column.add_attribute(&cell, "text", index);
+            view_column.set_expand(index == 1);
+            self.tree_view.append_column(&view_column);
+        }
+    }
+
+    fn model() -> Model {
+        Model {
+            visible: false,
+        }
+    }
+
+    /// Show the continuations.
+    fn set_continuations(&mut self, continuations: Vec<Continuation>) {
+        let model = ListStore::new(&[Type::String, Type::String]);
+        for (keys, action) in &continuations {
+            let row = model.insert(-1);
+            model.set_value(&row, 0, &keys.to_value());
+            model.set_value(&row, 1, &action.to_value());
+        }
+        let policy =
+            if continuations.len() < 2 {
+                Never
+            }
+            else {
+                Automatic
+            };
+        self.scrolled_window.set_policy(Never, policy);
+        self.tree_view.set_model(Some(&model));
+        self.model.visible = !continuations.is_empty();
+    }
+
+    fn update(&mut self, msg: Msg) {
+        match msg {
+            Continuations(continuations) => self.set_continuations(continuations),
+            Visible(visible) => self.model.visible = visible,
+        }
+    }
+
+    view! {
+        #[name="scrolled_window"]
+        gtk::ScrolledWindow {
+            max_content_height: WHICH_KEY_VIEW_MAX_HEIGHT,
+            propagate_natural_height: true,
+            valign: Align::End,
+            visible: self.model.visible,
+            #[name="tree_view"]
+            gtk::TreeView {
+                can_focus: false,
+                enable_search: false,
+                headers_visible: false,
+            }
+        }
+    }
+}
@@ -24,7 +24,9 @@ use std::marker::PhantomData;
 
 use mg_settings::{EnumMetaData, SettingCompletion};
 
+use app::{BUILTIN_COMMANDS, BUILTIN_SETTINGS, MappingDescription};
 use completion::{Completer, CompletionResult};
+use completion::Column::{self, Expand};
 
 /// A command completer.
 pub struct CommandCompleter<T: Clone> {
@@ -41,15 +43,22 @@ impl<T: Clone + EnumMetaData> CommandCompleter<T> {
                 .filter(|&(_, metadata)| !metadata.completion_hidden)
                 .map(|(setting_name, metadata)| (setting_name.clone(), metadata.help_text.clone()))
                 .collect();
-        data.push(("map".to_string(), "Create a new key binding".to_string()));
-        data.push(("set".to_string(), "Change the value of a setting".to_string()));
-        data.push(("unmap".to_string(), "Delete a key binding".to_string()));
+        data.extend(BUILTIN_COMMANDS.iter().map(|&(name, help)| (name.to_string(), help.to_string())));
         data.sort();
         CommandCompleter {
             metadata: data,
             _phantom: PhantomData,
         }
     }
+
+    /// Create a new command completer which also completes the aliases.
+    /// The command of an alias is shown as its help text.
+    pub fn with_aliases(aliases: &HashMap<String, String>) -> CommandCompleter<T> {
+        let mut completer = Self::new();
+        completer.metadata.extend(aliases.iter().map(|(name, command)| (name.clone(), command.clone())));
+        completer.metadata.sort();
+        completer
+    }
 }
 
 impl<T: Clone> Completer for CommandCompleter<T> {
@@ -63,6 +72,86 @@ impl<T: Clone> Completer for CommandCompleter<T> {
     }
 }
 
+/// A mapping completer, listing the key bindings.
+/// The first word of the input keeps only the mappings of the mode with this name when there is such a mode and
+/// each word must be found in the mode, the keys, the action or the source of the mapping.
+pub struct MappingCompleter {
+    mappings: Vec<MappingDescription>,
+}
+
+impl MappingCompleter {
+    /// Create a new mapping completer.
+    pub fn new(mappings: Vec<MappingDescription>) -> Self {
+        MappingCompleter {
+            mappings,
+        }
+    }
+}
+
+impl Completer for MappingCompleter {
+    fn columns(&self) -> Vec<Column> {
+        vec![Expand, Expand, Expand, Expand]
+    }
+
+    fn complete_result(&self, value: &str) -> String {
+        format!("map {}", value)
+    }
+
+    fn completions(&mut self, input: &str) -> Vec<CompletionResult> {
+        let input = input.to_lowercase();
+        let mut words: Vec<_> = input.split_whitespace().collect();
+        let mode =
+            match words.first() {
+                Some(&word) if self.mappings.iter().any(|mapping| mapping.mode == word) => Some(word),
+                _ => None,
+            };
+        if mode.is_some() {
+            words.remove(0);
+        }
+        self.mappings.iter()
+            .filter(|mapping| mode.map_or(true, |mode| mapping.mode == mode))
+            .filter(|mapping| {
+                let source = mapping.source.name();
+                let columns = [&mapping.mode, &mapping.keys, &mapping.action];
+                words.iter().all(|word|
+                    source.contains(word) || columns.iter().any(|column| column.to_lowercase().contains(word)))
+            })
+            .map(|mapping|
+                 CompletionResult::new(&[&mapping.keys, &mapping.action, &mapping.mode, mapping.source.name()]))
+            .collect()
+    }
+}
+
+/// A help completer, completing the help topics.
+pub struct HelpCompleter {
+    topics: Vec<(String, String)>,
+}
+
+impl HelpCompleter {
+    /// Create a new help completer from the topics with their description.
+    pub fn new(topics: Vec<(String, String)>) -> Self {
+        HelpCompleter {
+            topics,
+        }
+    }
+}
+
+impl Completer for HelpCompleter {
+    fn complete_result(&self, value: &str) -> String {
+        format!("help {}", value)
+    }
+
+    fn completions(&mut self, input: &str) -> Vec<CompletionResult> {
+        let input = input.trim().to_lowercase();
+        self.topics.iter()
+            .filter(|&&(ref topic, ref description)|
+                    topic.to_lowercase().contains(&input) ||
+                    description.to_lowercase().contains(&input))
+            .map(|&(ref col1, ref col2)| CompletionResult::new(&[col1, col2]))
+            .collect()
+    }
+}
+
 /// A nop completer.
 pub struct NoCompleter {
 }
@@ -103,6 +192,7 @@ impl<T: EnumMetaData + SettingCompletion> SettingCompleter<T> {
                 .filter(|&(_, metadata)| !metadata.completion_hidden)
                 .map(|(setting_name, metadata)| (setting_name.clone(), metadata.help_text.clone()))
                 .collect();
+        data.extend(BUILTIN_SETTINGS.iter().map(|&(name, help)| (name.to_string(), help.to_string())));
         data.sort();
         SettingCompleter {
             selected_name: None,
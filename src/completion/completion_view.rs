@@ -46,9 +46,9 @@ use pango::EllipsizeMode;
 use relm::{Relm, Widget};
 use relm_derive::widget;
 
-use app::COMMAND_MODE;
 use completion::Completers;
 use completion::Column::{self, Expand};
+use engine::COMMAND_MODE;
 use self::Msg::*;
 use super::{Completer, Completion, DEFAULT_COMPLETER_IDENT, NO_COMPLETER_IDENT};
 
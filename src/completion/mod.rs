@@ -39,7 +39,7 @@ use gtk::{
 };
 
 use self::Column::Expand;
-pub use self::completers::{CommandCompleter, NoCompleter, SettingCompleter};
+pub use self::completers::{CommandCompleter, HelpCompleter, MappingCompleter, NoCompleter, SettingCompleter};
 pub use self::completion_view::CompletionView;
 
 /// The identifier of the default completer.
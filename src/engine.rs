@@ -0,0 +1,1192 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! The key handling which does not depend on GTK: the modes entered, the keys typed with the count typed before
+//! them and the mappings, commands and operations they correspond to.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Keys;
+use std::mem;
+
+use mg_settings::errors::Result;
+
+use key::Key::{self, Char, Escape};
+use key_parser::{keys_to_string, parse_keys};
+use self::Action::{
+    CompleteCommand,
+    EnterCommandMode,
+    EnterSpecialCommandMode,
+    ModeChanged,
+    Reset,
+    StartTimer,
+    TypeKey,
+    WhichKey,
+};
+use self::MappingAction::Keys as KeySequence;
+use self::ShortcutCommand::{Complete, Incomplete};
+
+pub const BLOCKING_INPUT_MODE: &str = "blocking-input";
+pub const COMMAND_MODE: &str = "command";
+pub const DEFAULT_MAPPING_TIMEOUT: u32 = 0;
+pub const DEFAULT_WHICH_KEY_TIMEOUT: u32 = 500;
+pub const INPUT_MODE: &str = "input";
+/// The maximum number of mappings expanded before the fed keys run out, to stop recursive mappings.
+const MAX_MAPPING_EXPANSIONS: u32 = 1000;
+pub const NORMAL_MODE: &str = "normal";
+pub const OPERATOR_MODE: &str = "operator-pending";
+
+/// What the application does after the engine handled a key or a timer.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Execute the command of a mapping, with the count typed before the mapping.
+    /// `keys_len` is the number of keys typed for the mapping, including the count.
+    Command {
+        command: String,
+        count: Option<u32>,
+        keys_len: usize,
+    },
+    /// Show the command of a mapping in the command entry for the user to complete it, with the count typed
+    /// before the mapping.
+    CompleteCommand(String, Option<u32>),
+    /// Show the command entry to type a command.
+    EnterCommandMode,
+    /// Show the command entry to type a special command starting with the identifier (i.e. '/').
+    EnterSpecialCommandMode(char),
+    Error(String),
+    /// The current mode changed: this is the mode which was left.
+    ModeChanged(String),
+    /// An operator was typed with its motion.
+    Operation(Operation),
+    /// Hide the command entry and the message: the keys typed were a mapping or cannot become one.
+    Reset,
+    /// Call `Engine::timeout()` with the timer after the delay in milliseconds.
+    StartTimer(Timer, u32),
+    /// Type the key as if the user typed it, then call `Engine::next_fed_key()` once it is handled.
+    /// The key is given to `Engine::fed_key()` when it reaches the engine.
+    TypeKey(Key),
+    /// Show the mappings continuing the keys typed, as the keys to type next and the action, or hide them when
+    /// the list is empty.
+    WhichKey(Vec<(String, String)>),
+}
+
+/// A key waiting to be fed, with whether it can trigger key sequence mappings.
+enum FedKey {
+    Key(Key, bool),
+    /// Keys to type the specified number of times.
+    /// Only one repetition is added to the keys waiting to be fed at a time, to avoid holding all the keys for a large
+    /// count.
+    Repeat(Vec<Key>, bool, u32),
+}
+
+/// The right-hand side of a mapping.
+#[derive(Clone, Debug)]
+pub struct Mapping {
+    /// The command or the keys to type.
+    pub action: String,
+    /// Whether the keys can be typed at their position on a US layout when the layout does not have them.
+    pub keycode_fallback: bool,
+    /// Whether the keys of the action can trigger other key sequence mappings.
+    /// The mappings to commands are always triggered.
+    pub recursive: bool,
+    pub source: MappingSource,
+}
+
+impl Mapping {
+    /// Create a new mapping.
+    pub fn new(action: String, keycode_fallback: bool, recursive: bool, source: MappingSource) -> Self {
+        Mapping {
+            action,
+            keycode_fallback,
+            recursive,
+            source,
+        }
+    }
+}
+
+/// What happens when the keys of a mapping are typed.
+#[derive(Debug, PartialEq)]
+pub enum MappingAction {
+    /// Execute a command.
+    Command(ShortcutCommand),
+    /// Type the keys.
+    Keys(Vec<Key>),
+}
+
+/// Where a mapping was created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MappingSource {
+    /// The config file of the application, or a file it includes, when starting or reloading the config.
+    Config,
+    /// The command entry, an event or a mapping.
+    Runtime,
+    /// A file executed with the source command.
+    Sourced,
+}
+
+impl MappingSource {
+    /// Get the name of the source shown in the mapping list.
+    pub fn name(self) -> &'static str {
+        match self {
+            MappingSource::Config => "config",
+            MappingSource::Runtime => "runtime",
+            MappingSource::Sourced => "source",
+        }
+    }
+}
+
+/// An operator applied to a motion, sent to the application.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    /// The count typed before the operator multiplied by the count typed before the motion, if any.
+    pub count: Option<u32>,
+    /// The name of the motion, or None when the operator was doubled (i.e. `dd`) to act on whole lines.
+    pub motion: Option<String>,
+    /// The name of the operator.
+    pub operator: String,
+}
+
+/// What the keys typed correspond to in the normal mode: a mapping or an operator.
+#[derive(Clone)]
+enum ShortcutAction {
+    Mapping(Mapping),
+    /// The name of the operator.
+    Operator(String),
+}
+
+/// An operator waiting for its motion.
+struct PendingOperator {
+    count: Option<u32>,
+    keys: Vec<Key>,
+    name: String,
+    /// The keys typed since entering the operator-pending mode.
+    typed_keys: Vec<Key>,
+}
+
+/// A command from a map command.
+#[derive(Debug, PartialEq)]
+pub enum ShortcutCommand {
+    /// A complete command that is to be executed.
+    Complete(String),
+    /// An incomplete command where the user needs to complete it and press Enter.
+    Incomplete(String),
+}
+
+/// Convert an action String to a command String.
+pub fn action_to_command(action: &str) -> ShortcutCommand {
+    if let Some(':') = action.chars().next() {
+        if let Some(index) = action.find("<Enter>") {
+            Complete(action[1..index].to_string())
+        }
+        else {
+            Incomplete(action[1..].to_string())
+        }
+    }
+    else {
+        Complete(action.to_string())
+    }
+}
+
+/// A timer started by the engine, with the generation of the keys typed when it started.
+/// The timer does nothing if other keys were typed since then.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timer {
+    /// Execute the mapping which waits for the keys of a longer mapping.
+    Mapping(u32),
+    /// Forget the keys typed which are not yet a mapping.
+    PendingShortcut(u32),
+    /// Show the mappings continuing the keys typed.
+    WhichKey(u32),
+}
+
+/// Values indexed by sequences of keys (i.e. the mappings of a mode), stored in a prefix tree.
+/// Finding the value of keys, checking if keys are the start of longer sequences and listing these sequences take
+/// a time proportional to the number of keys, not to the number of sequences.
+#[derive(Clone)]
+pub struct KeyTrie<V> {
+    children: HashMap<Key, KeyTrie<V>>,
+    value: Option<V>,
+}
+
+impl<V> Default for KeyTrie<V> {
+    fn default() -> Self {
+        KeyTrie::new()
+    }
+}
+
+impl<V> KeyTrie<V> {
+    pub fn new() -> Self {
+        KeyTrie {
+            children: HashMap::new(),
+            value: None,
+        }
+    }
+
+    /// Get the sequences of keys with their value.
+    pub fn entries(&self) -> Vec<(Vec<Key>, &V)> {
+        self.entries_starting_with(&[])
+    }
+
+    /// Get the sequences of keys starting with the keys (including the keys themselves) with their value.
+    /// The keys returned are the keys following the given keys, so they are empty for the value of these keys.
+    pub fn entries_starting_with(&self, keys: &[Key]) -> Vec<(Vec<Key>, &V)> {
+        let mut entries = vec![];
+        if let Some(node) = self.node(keys) {
+            node.collect_entries(&mut vec![], &mut entries);
+        }
+        entries
+    }
+
+    /// Get the first key of the sequences.
+    pub fn first_keys(&self) -> Keys<'_, Key, KeyTrie<V>> {
+        self.children.keys()
+    }
+
+    pub fn get(&self, keys: &[Key]) -> Option<&V> {
+        self.node(keys).and_then(|node| node.value.as_ref())
+    }
+
+    /// Check if there are sequences starting with the keys, including the keys themselves.
+    pub fn has_keys_starting_with(&self, keys: &[Key]) -> bool {
+        self.node(keys).map_or(false, |node| node.value.is_some() || !node.children.is_empty())
+    }
+
+    /// Check if there are sequences longer than the keys which start with them.
+    pub fn has_longer_keys(&self, keys: &[Key]) -> bool {
+        self.node(keys).map_or(false, |node| !node.children.is_empty())
+    }
+
+    /// Set the value of the keys, returning the previous value.
+    pub fn insert(&mut self, keys: Vec<Key>, value: V) -> Option<V> {
+        let mut node = self;
+        for key in keys {
+            node = node.children.entry(key).or_insert_with(KeyTrie::new);
+        }
+        node.value.replace(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    /// Remove the value of the keys, returning it.
+    /// The nodes which do not lead to a value anymore are removed.
+    pub fn remove(&mut self, keys: &[Key]) -> Option<V> {
+        match keys.split_first() {
+            None => self.value.take(),
+            Some((key, rest)) => {
+                let (value, remove_child) =
+                    match self.children.get_mut(key) {
+                        Some(child) => {
+                            let value = child.remove(rest);
+                            (value, child.is_empty())
+                        },
+                        None => return None,
+                    };
+                if remove_child {
+                    self.children.remove(key);
+                }
+                value
+            },
+        }
+    }
+
+    /// Add the sequences of this node, prefixed by the keys leading to it, to the entries.
+    fn collect_entries<'a>(&'a self, prefix: &mut Vec<Key>, entries: &mut Vec<(Vec<Key>, &'a V)>) {
+        if let Some(ref value) = self.value {
+            entries.push((prefix.clone(), value));
+        }
+        for (key, child) in &self.children {
+            prefix.push(key.clone());
+            child.collect_entries(prefix, entries);
+            prefix.pop();
+        }
+    }
+
+    /// Get the node reached by following the keys.
+    fn node(&self, keys: &[Key]) -> Option<&KeyTrie<V>> {
+        let mut node = self;
+        for key in keys {
+            node = node.children.get(key)?;
+        }
+        Some(node)
+    }
+}
+
+/// The current mode and the modes to go back to when leaving it.
+pub struct ModeStack {
+    /// The mode entered when there is no mode to go back to.
+    base_mode: String,
+    current: String,
+    /// The modes to go back to, the last one being the previous mode.
+    previous: Vec<String>,
+}
+
+impl ModeStack {
+    /// Create a mode stack in the base mode, which is entered when there is no mode to go back to.
+    pub fn new(base_mode: &str) -> Self {
+        ModeStack {
+            base_mode: base_mode.to_string(),
+            current: base_mode.to_string(),
+            previous: vec![],
+        }
+    }
+
+    /// Get the name of the current mode.
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// Go back to the previous mode (the base mode if there is none).
+    /// Returns the mode which was left.
+    pub fn pop(&mut self) -> String {
+        let mode = self.previous.pop().unwrap_or_else(|| self.base_mode.clone());
+        self.set(&mode)
+    }
+
+    /// Enter the mode and remember the current mode to go back to it when this mode is left.
+    /// Returns the mode which was left, or None if the mode is already the current mode.
+    pub fn push(&mut self, mode: &str) -> Option<String> {
+        if self.current == mode {
+            return None;
+        }
+        let previous_mode = self.set(mode);
+        self.previous.push(previous_mode.clone());
+        Some(previous_mode)
+    }
+
+    /// Enter the mode and forget the modes to go back to.
+    /// Returns the mode which was left.
+    pub fn replace(&mut self, mode: &str) -> String {
+        self.previous.clear();
+        self.set(mode)
+    }
+
+    /// Enter the mode without changing the modes to go back to.
+    /// Returns the mode which was left.
+    pub fn set(&mut self, mode: &str) -> String {
+        mem::replace(&mut self.current, mode.to_string())
+    }
+}
+
+/// The keys typed which are not yet a mapping, with the count typed before them (i.e. `3g`).
+#[derive(Clone, Default)]
+pub struct Shortcut {
+    keys: Vec<Key>,
+}
+
+impl Shortcut {
+    pub fn new() -> Self {
+        Shortcut {
+            keys: vec![],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Get the count typed before the keys of the mapping.
+    pub fn count(&self) -> Option<u32> {
+        let mut digits = self.keys.iter()
+            .take_while(is_digit)
+            .peekable();
+        if digits.peek().is_none() {
+            None
+        }
+        else {
+            let num = digits
+                .fold(0u32, |num, key| {
+                    if let Char(c) = *key {
+                        if let Some(digit) = c.to_digit(10) {
+                            return num.saturating_mul(10).saturating_add(digit);
+                        }
+                    }
+                    num
+                });
+            Some(num)
+        }
+    }
+
+    /// Check if the keys are the start of a longer mapping of the mode.
+    pub fn has_longer_mapping<V>(&self, mappings: &KeyTrie<V>) -> bool {
+        mappings.has_longer_keys(self.mapping_keys())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Check if the keys are the start of a mapping of the mode, or a mapping.
+    pub fn is_mapping_start<V>(&self, mappings: &KeyTrie<V>) -> bool {
+        mappings.has_keys_starting_with(self.mapping_keys())
+    }
+
+    /// Get all the keys typed, including the count.
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    /// Get the mapping of the mode corresponding to the keys typed, if any.
+    pub fn mapping<'a, V>(&self, mappings: &'a KeyTrie<V>) -> Option<&'a V> {
+        mappings.get(self.mapping_keys())
+    }
+
+    /// Get the keys typed without the count typed before them.
+    pub fn mapping_keys(&self) -> &[Key] {
+        let first = self.keys.first().cloned().unwrap_or(Char('0'));
+        let start =
+            if first == Char('0') {
+                0
+            }
+            else {
+                self.keys.iter()
+                    .position(is_not_digit)
+                    .unwrap_or_else(|| self.keys.len())
+            };
+        &self.keys[start..]
+    }
+
+    pub fn pop(&mut self) -> Option<Key> {
+        self.keys.pop()
+    }
+
+    pub fn push(&mut self, key: Key) {
+        self.keys.push(key);
+    }
+}
+
+fn is_digit(key: &&Key) -> bool {
+    !is_not_digit(key)
+}
+
+fn is_not_digit(key: &Key) -> bool {
+    if let Char(c) = *key {
+        !c.to_digit(10).is_some()
+    }
+    else {
+        true
+    }
+}
+
+/// The state machine turning the keys typed into the actions of the mappings, the commands and the operations.
+/// It does not depend on GTK: each key, fed key and timer returns the actions the application has to do, like
+/// executing a command or starting a timer, which calls `timeout()` when it runs out.
+pub struct Engine {
+    /// The mapping or operator typed which waits for the keys of a longer mapping.
+    ambiguous_action: Option<ShortcutAction>,
+    /// The names of the commands, to know if the action of a mapping is a command.
+    command_names: HashSet<String>,
+    /// Whether the fed key being typed can trigger key sequence mappings.
+    fed_key_remap: bool,
+    /// The keys waiting to be fed.
+    fed_keys: VecDeque<FedKey>,
+    feeding_keys: bool,
+    /// Whether a character starts a special command in the normal mode.
+    is_identifier: fn(char) -> bool,
+    /// Whether all the mappings can be typed with the keys at their position on a US layout.
+    keycode_fallback: bool,
+    mapping_expansions: u32,
+    mapping_timeout: u32,
+    /// The mappings of each mode, by mode name.
+    mappings: HashMap<String, KeyTrie<Mapping>>,
+    mode_stack: ModeStack,
+    /// The motions given by the application, by keys.
+    motions: HashMap<Vec<Key>, String>,
+    /// The operators given by the application, by keys.
+    operators: HashMap<Vec<Key>, String>,
+    pending_operator: Option<PendingOperator>,
+    pending_shortcut_timeout: u32,
+    shortcut: Shortcut,
+    /// Incremented each time the shortcut changes to ignore the timers of the previous shortcuts.
+    shortcut_generation: u32,
+    which_key_shown: bool,
+    /// The time in milliseconds to wait before showing the continuations of the shortcut.
+    which_key_timeout: u32,
+}
+
+impl Engine {
+    /// Create an engine in the normal mode, without mappings.
+    /// The characters for which `is_identifier` returns true start a special command in the normal mode.
+    pub fn new(is_identifier: fn(char) -> bool) -> Self {
+        Engine {
+            ambiguous_action: None,
+            command_names: HashSet::new(),
+            fed_key_remap: true,
+            fed_keys: VecDeque::new(),
+            feeding_keys: false,
+            is_identifier,
+            keycode_fallback: false,
+            mapping_expansions: 0,
+            mapping_timeout: DEFAULT_MAPPING_TIMEOUT,
+            mappings: HashMap::new(),
+            mode_stack: ModeStack::new(NORMAL_MODE),
+            motions: HashMap::new(),
+            operators: HashMap::new(),
+            pending_operator: None,
+            pending_shortcut_timeout: 0,
+            shortcut: Shortcut::new(),
+            shortcut_generation: 0,
+            which_key_shown: false,
+            which_key_timeout: DEFAULT_WHICH_KEY_TIMEOUT,
+        }
+    }
+
+    /// Add a mapping to the mode with this name, replacing the mapping of the same keys.
+    pub fn add_mapping(&mut self, mode: &str, keys: Vec<Key>, mapping: Mapping) {
+        self.mappings.entry(mode.to_string())
+            .or_insert_with(KeyTrie::new)
+            .insert(keys, mapping);
+    }
+
+    /// Forget the keys typed which are not yet a mapping.
+    pub fn clear_shortcut(&mut self) -> Vec<Action> {
+        let mut actions = vec![];
+        self.forget_shortcut(&mut actions);
+        actions
+    }
+
+    /// Get the mappings of the current mode.
+    pub fn current_mappings(&self) -> Option<&KeyTrie<Mapping>> {
+        let mut current_mode = self.mode_stack.current();
+        // The input modes have the same mappings as the command mode.
+        if current_mode == INPUT_MODE || current_mode == BLOCKING_INPUT_MODE {
+            current_mode = COMMAND_MODE;
+        }
+        self.mappings.get(current_mode)
+    }
+
+    /// Get the name of the current mode.
+    pub fn current_mode(&self) -> &str {
+        self.mode_stack.current()
+    }
+
+    /// Handle a key given by the `TypeKey` action.
+    pub fn fed_key(&mut self, key: Key) -> Vec<Action> {
+        let remap = self.fed_key_remap;
+        self.handle_key(key, remap)
+    }
+
+    /// Type the keys the specified number of times before the keys which are still being fed.
+    /// When remap is false, the keys do not trigger the key sequence mappings.
+    pub fn feed_keys(&mut self, keys: Vec<Key>, count: u32, remap: bool) -> Vec<Action> {
+        let mut actions = vec![];
+        if count > 1 && !keys.is_empty() {
+            self.fed_keys.push_front(FedKey::Repeat(keys.clone(), remap, count - 1));
+        }
+        self.feed_keys_first(keys, remap, &mut actions);
+        actions
+    }
+
+    /// Handle a key typed by the user.
+    pub fn key(&mut self, key: Key) -> Vec<Action> {
+        self.handle_key(key, true)
+    }
+
+    /// Check if all the mappings can be typed with the keys at their position on a US layout.
+    pub fn keycode_fallback(&self) -> bool {
+        self.keycode_fallback
+    }
+
+    /// Get the mappings of each mode, by mode name.
+    pub fn mappings(&self) -> &HashMap<String, KeyTrie<Mapping>> {
+        &self.mappings
+    }
+
+    /// Type the next fed key, once the previous one is handled.
+    pub fn next_fed_key(&mut self) -> Vec<Action> {
+        let mut actions = vec![];
+        self.type_next_key(&mut actions);
+        actions
+    }
+
+    /// Go back to the previous mode (the normal mode if there is none).
+    /// Returns the mode which was left.
+    pub fn pop_mode(&mut self) -> String {
+        self.mode_stack.pop()
+    }
+
+    /// Enter the mode and remember the current mode to go back to it when this mode is left.
+    /// Returns the mode which was left, or None if the mode is already the current mode.
+    pub fn push_mode(&mut self, mode: &str) -> Option<String> {
+        self.mode_stack.push(mode)
+    }
+
+    /// Remove the mapping of the keys from the mode with this name.
+    pub fn remove_mapping(&mut self, mode: &str, keys: &[Key]) {
+        if let Some(mappings) = self.mappings.get_mut(mode) {
+            mappings.remove(keys);
+        }
+    }
+
+    /// Replace the mappings of all the modes, returning the previous mappings.
+    pub fn replace_mappings(&mut self, mappings: HashMap<String, KeyTrie<Mapping>>)
+        -> HashMap<String, KeyTrie<Mapping>>
+    {
+        mem::replace(&mut self.mappings, mappings)
+    }
+
+    /// Enter the mode and forget the modes to go back to.
+    /// Returns the mode which was left.
+    pub fn replace_modes(&mut self, mode: &str) -> String {
+        self.mode_stack.replace(mode)
+    }
+
+    /// Set the names of the commands: a recursive mapping whose action starts with one of them executes it.
+    pub fn set_command_names(&mut self, command_names: HashSet<String>) {
+        self.command_names = command_names;
+    }
+
+    /// Set whether all the mappings can be typed with the keys at their position on a US layout.
+    pub fn set_keycode_fallback(&mut self, keycode_fallback: bool) {
+        self.keycode_fallback = keycode_fallback;
+    }
+
+    /// Set the time in milliseconds to wait for a longer mapping before executing a mapping which is also the
+    /// start of a longer one (0 to execute it right away).
+    pub fn set_mapping_timeout(&mut self, timeout: u32) {
+        self.mapping_timeout = timeout;
+    }
+
+    /// Set the motions which can follow an operator, by keys.
+    pub fn set_motions(&mut self, motions: HashMap<Vec<Key>, String>) {
+        self.motions = motions;
+    }
+
+    /// Set the operators, by keys.
+    pub fn set_operators(&mut self, operators: HashMap<Vec<Key>, String>) {
+        self.operators = operators;
+    }
+
+    /// Set the time in milliseconds after which the keys typed which are not yet a mapping are forgotten (0 to
+    /// never forget them).
+    pub fn set_pending_shortcut_timeout(&mut self, timeout: u32) {
+        self.pending_shortcut_timeout = timeout;
+    }
+
+    /// Set the time in milliseconds to wait before showing the mappings continuing the keys typed (0 to never
+    /// show them).
+    pub fn set_which_key_timeout(&mut self, timeout: u32) {
+        self.which_key_timeout = timeout;
+    }
+
+    /// Get the keys typed which are not yet a mapping.
+    pub fn shortcut(&self) -> &Shortcut {
+        &self.shortcut
+    }
+
+    /// Choose the key added to the shortcut from the key typed and the key at the same position on a US layout.
+    /// When the key typed does not continue any mapping, the key on a US layout is used instead if it continues
+    /// a mapping which allows it.
+    /// The keys entering the command mode from the normal mode are never replaced.
+    pub fn shortcut_key(&self, key: Option<Key>, keycode_key: Option<Key>) -> Option<Key> {
+        if key.as_ref().map_or(false, |key| self.enters_command_mode(key) || self.continues_mapping(key, false)) {
+            return key;
+        }
+        match keycode_key {
+            Some(ref keycode_key) if self.continues_mapping(keycode_key, true) => Some(keycode_key.clone()),
+            _ => key,
+        }
+    }
+
+    /// Get the keys to show to the user: the keys typed or, in the operator-pending mode, the operator and the
+    /// keys typed after it.
+    pub fn shown_keys(&self) -> Vec<Key> {
+        match self.pending_operator {
+            Some(ref operator) => operator.keys.iter().chain(&operator.typed_keys).cloned().collect(),
+            None => self.shortcut.keys().to_vec(),
+        }
+    }
+
+    /// Handle a timer started by the `StartTimer` action.
+    pub fn timeout(&mut self, timer: Timer) -> Vec<Action> {
+        let mut actions = vec![];
+        match timer {
+            Timer::Mapping(generation) if generation == self.shortcut_generation => {
+                if let Some(action) = self.ambiguous_action.take() {
+                    self.execute_shortcut_action(action, &mut actions);
+                }
+            },
+            Timer::PendingShortcut(generation)
+                if generation == self.shortcut_generation && !self.shortcut.is_empty() =>
+                self.forget_shortcut(&mut actions),
+            Timer::WhichKey(generation) if generation == self.shortcut_generation =>
+                self.update_which_key(&mut actions),
+            _ => (),
+        }
+        actions
+    }
+
+    /// Type the keys after the keys which are still being fed, as if the user typed them.
+    pub fn type_keys(&mut self, keys: Vec<Key>) -> Vec<Action> {
+        let mut actions = vec![];
+        self.fed_keys.extend(keys.into_iter().map(|key| FedKey::Key(key, true)));
+        if !self.feeding_keys {
+            self.type_next_key(&mut actions);
+        }
+        actions
+    }
+
+    fn add_to_shortcut(&mut self, key: Key) {
+        self.shortcut.push(key);
+        self.shortcut_generation = self.shortcut_generation.wrapping_add(1);
+    }
+
+    /// Leave the operator-pending mode without sending the operation.
+    fn cancel_operator(&mut self, actions: &mut Vec<Action>) {
+        self.pending_operator = None;
+        actions.push(ModeChanged(self.mode_stack.pop()));
+    }
+
+    /// Check if the key continues the current shortcut to a mapping of the current mode.
+    /// When keycode is true, only the mappings which can be typed with the keycode fallback are considered.
+    fn continues_mapping(&self, key: &Key, keycode: bool) -> bool {
+        let mut shortcut = self.shortcut.mapping_keys().to_vec();
+        shortcut.push(key.clone());
+        self.current_mappings()
+            .map(|mappings| {
+                if !keycode || self.keycode_fallback {
+                    mappings.has_keys_starting_with(&shortcut)
+                }
+                else {
+                    mappings.entries_starting_with(&shortcut).iter().any(|&(_, mapping)| mapping.keycode_fallback)
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    /// Check if the key shows the command entry (i.e. `:` or `/`) in the current mode.
+    fn enters_command_mode(&self, key: &Key) -> bool {
+        match *key {
+            Char(character) => self.mode_stack.current() == NORMAL_MODE &&
+                (character == ':' || (self.is_identifier)(character)),
+            _ => false,
+        }
+    }
+
+    /// Execute the action of the mapping corresponding to the current shortcut.
+    fn execute_mapping(&mut self, mapping: &Mapping, actions: &mut Vec<Action>) {
+        let count = self.shortcut.count();
+        let keys_len = self.shortcut.keys().len();
+        actions.push(Reset);
+        self.forget_shortcut(actions);
+        match self.mapping_action(mapping) {
+            Ok(MappingAction::Command(Complete(command))) => actions.push(Action::Command {
+                command,
+                count,
+                keys_len,
+            }),
+            Ok(MappingAction::Command(Incomplete(command))) => actions.push(CompleteCommand(command, count)),
+            Ok(KeySequence(keys)) => self.feed_keys_first(keys, mapping.recursive, actions),
+            Err(error) => actions.push(Action::Error(error.to_string())),
+        }
+    }
+
+    /// Add the keys in front of the keys waiting to be fed and start feeding them.
+    fn feed_keys_first(&mut self, keys: Vec<Key>, remap: bool, actions: &mut Vec<Action>) {
+        self.mapping_expansions += 1;
+        if self.mapping_expansions > MAX_MAPPING_EXPANSIONS {
+            self.fed_keys.clear();
+            actions.push(Action::Error("Recursive mapping".to_string()));
+            return;
+        }
+        for key in keys.into_iter().rev() {
+            self.fed_keys.push_front(FedKey::Key(key, remap));
+        }
+        if !self.feeding_keys {
+            self.type_next_key(actions);
+        }
+    }
+
+    /// Send the operation to the application and leave the operator-pending mode.
+    fn finish_operator(&mut self, motion_count: Option<u32>, motion: Option<String>, actions: &mut Vec<Action>) {
+        if let Some(operator) = self.pending_operator.take() {
+            actions.push(ModeChanged(self.mode_stack.pop()));
+            actions.push(Action::Operation(Operation {
+                count: total_count(operator.count, motion_count),
+                motion,
+                operator: operator.name,
+            }));
+        }
+    }
+
+    /// Forget the keys typed and the mapping waiting for a longer mapping, and hide their continuations.
+    fn forget_shortcut(&mut self, actions: &mut Vec<Action>) {
+        self.shortcut.clear();
+        self.ambiguous_action = None;
+        self.shortcut_generation = self.shortcut_generation.wrapping_add(1);
+        if self.which_key_shown {
+            self.which_key_shown = false;
+            actions.push(WhichKey(vec![]));
+        }
+    }
+
+    /// Handle a key typed in the current mode.
+    /// When remap is false, the key sequence mappings are ignored.
+    fn handle_key(&mut self, key: Key, remap: bool) -> Vec<Action> {
+        let mut actions = vec![];
+        match key {
+            Char(':') if self.enters_command_mode(&key) => actions.push(EnterCommandMode),
+            Char(character) if self.enters_command_mode(&key) => actions.push(EnterSpecialCommandMode(character)),
+            _ if self.mode_stack.current() == OPERATOR_MODE => self.handle_operator_key(key, &mut actions),
+            _ => self.handle_shortcut_key(key, remap, &mut actions),
+        }
+        actions
+    }
+
+    /// Handle a key typed in the operator-pending mode.
+    /// The keys, after an optional count, are the operator again, a motion or a mapping of the operator-pending
+    /// mode, whose action can be the name of a motion.
+    fn handle_operator_key(&mut self, key: Key, actions: &mut Vec<Action>) {
+        if key == Escape {
+            self.cancel_operator(actions);
+            return;
+        }
+        let (operator_keys, typed_keys) =
+            match self.pending_operator {
+                Some(ref mut operator) => {
+                    operator.typed_keys.push(key);
+                    (operator.keys.clone(), operator.typed_keys.clone())
+                },
+                None => return,
+            };
+        let (motion_count, keys) = split_count(&typed_keys);
+        if keys.is_empty() {
+            return;
+        }
+        if keys == &operator_keys[..] {
+            self.finish_operator(motion_count, None, actions);
+            return;
+        }
+        if let Some(motion) = self.motions.get(keys).cloned() {
+            self.finish_operator(motion_count, Some(motion), actions);
+            return;
+        }
+        let mapping = self.mappings.get(OPERATOR_MODE).and_then(|mappings| mappings.get(keys)).cloned();
+        if let Some(mapping) = mapping {
+            if self.motions.values().any(|motion| *motion == mapping.action) {
+                self.finish_operator(motion_count, Some(mapping.action), actions);
+                return;
+            }
+            if let Some(ref mut operator) = self.pending_operator {
+                operator.typed_keys.truncate(typed_keys.len() - keys.len());
+            }
+            match self.mapping_action(&mapping) {
+                Ok(MappingAction::Command(Complete(command))) => {
+                    self.cancel_operator(actions);
+                    actions.push(Action::Command {
+                        command,
+                        count: motion_count,
+                        keys_len: typed_keys.len(),
+                    });
+                },
+                Ok(MappingAction::Command(Incomplete(command))) => {
+                    self.cancel_operator(actions);
+                    actions.push(CompleteCommand(command, None));
+                },
+                Ok(KeySequence(keys)) => self.feed_keys_first(keys, mapping.recursive, actions),
+                Err(error) => {
+                    self.cancel_operator(actions);
+                    actions.push(Action::Error(error.to_string()));
+                },
+            }
+            return;
+        }
+        let is_prefix = |candidate: &Vec<Key>| candidate.len() > keys.len() && candidate.starts_with(keys);
+        let has_longer_keys = is_prefix(&operator_keys) || self.motions.keys().any(&is_prefix) ||
+            self.mappings.get(OPERATOR_MODE).map_or(false, |mappings| mappings.has_longer_keys(keys));
+        if !has_longer_keys {
+            self.cancel_operator(actions);
+            actions.push(Action::Error(format!("Unknown motion: {}", keys_to_string(keys))));
+        }
+    }
+
+    /// Add the key to the current shortcut and execute the corresponding mapping or start the corresponding
+    /// operator, the mappings taking precedence over the operators.
+    /// When the mapping or the operator is also the start of a longer mapping, it is executed when the mapping
+    /// timeout runs out or when a key which does not continue the longer mapping is typed.
+    /// Since an operator waits for a motion anyway, it always waits for the next key in this case.
+    /// When remap is false, the key sequence mappings are ignored.
+    fn handle_shortcut_key(&mut self, key: Key, remap: bool, actions: &mut Vec<Action>) {
+        let ambiguous_action = self.ambiguous_action.take();
+        self.add_to_shortcut(key.clone());
+        let action = self.current_mappings()
+            .and_then(|mappings| self.shortcut.mapping(mappings).cloned())
+            .filter(|mapping| remap || !self.is_key_sequence(mapping))
+            .map(ShortcutAction::Mapping)
+            .or_else(|| self.operator().map(ShortcutAction::Operator));
+        if let Some(action) = action {
+            let is_operator =
+                match action {
+                    ShortcutAction::Operator(_) => true,
+                    ShortcutAction::Mapping(_) => false,
+                };
+            if (is_operator || remap && self.mapping_timeout > 0) && self.has_longer_mapping() {
+                self.ambiguous_action = Some(action);
+                if self.mapping_timeout > 0 {
+                    actions.push(StartTimer(Timer::Mapping(self.shortcut_generation), self.mapping_timeout));
+                }
+                self.which_key_key_added(actions);
+            }
+            else {
+                self.execute_shortcut_action(action, actions);
+            }
+        }
+        else if self.no_possible_shortcut() {
+            if let Some(action) = ambiguous_action {
+                // The previous keys will not become a longer mapping, so execute their mapping and
+                // handle this key after it, in the operator-pending mode if they are an operator.
+                self.shortcut.pop();
+                self.execute_shortcut_action(action, actions);
+                if self.mode_stack.current() == OPERATOR_MODE {
+                    self.handle_operator_key(key, actions);
+                }
+                else {
+                    self.handle_shortcut_key(key, remap, actions);
+                }
+                return;
+            }
+            // The keys typed in the input modes are the answer of the user.
+            if self.mode_stack.current() != INPUT_MODE {
+                actions.push(Reset);
+            }
+            self.forget_shortcut(actions);
+        }
+        else {
+            if self.pending_shortcut_timeout > 0 {
+                actions.push(StartTimer(Timer::PendingShortcut(self.shortcut_generation),
+                    self.pending_shortcut_timeout));
+            }
+            self.which_key_key_added(actions);
+        }
+    }
+
+    /// Execute the mapping or start the operator corresponding to the current shortcut.
+    fn execute_shortcut_action(&mut self, action: ShortcutAction, actions: &mut Vec<Action>) {
+        match action {
+            ShortcutAction::Mapping(mapping) => self.execute_mapping(&mapping, actions),
+            ShortcutAction::Operator(name) => self.start_operator(name, actions),
+        }
+    }
+
+    /// Check if the current shortcut is the start of a longer mapping or, in the normal mode, of a longer operator.
+    fn has_longer_mapping(&self) -> bool {
+        let keys = self.shortcut.mapping_keys();
+        let longer_operator = self.mode_stack.current() == NORMAL_MODE &&
+            self.operators.keys().any(|operator| operator.len() > keys.len() && operator.starts_with(keys));
+        longer_operator || self.current_mappings()
+            .map(|mappings| self.shortcut.has_longer_mapping(mappings))
+            .unwrap_or(false)
+    }
+
+    /// Check if the action of the mapping is a key sequence.
+    fn is_key_sequence(&self, mapping: &Mapping) -> bool {
+        match self.mapping_action(mapping) {
+            Ok(MappingAction::Command(_)) => false,
+            Ok(KeySequence(_)) | Err(_) => true,
+        }
+    }
+
+    /// Get what happens when the keys of the mapping are typed.
+    /// The action of a recursive mapping is a command when it is `:command<Enter>`, `:command` (to be completed
+    /// by the user) or starts with the name of a command.
+    /// Otherwise, and always for the non-recursive mappings, the action is a key sequence.
+    fn mapping_action(&self, mapping: &Mapping) -> Result<MappingAction> {
+        let action = &mapping.action;
+        if mapping.recursive {
+            if action.starts_with(':') {
+                let enter = "<Enter>";
+                match action.find(enter) {
+                    Some(index) if index + enter.len() < action.len() => (),
+                    _ => return Ok(MappingAction::Command(action_to_command(action))),
+                }
+            }
+            else if action.split_whitespace().next().map_or(false, |name| self.command_names.contains(name)) {
+                return Ok(MappingAction::Command(action_to_command(action)));
+            }
+        }
+        parse_keys(action).map(KeySequence)
+    }
+
+    /// Check if there are no possible shortcuts.
+    fn no_possible_shortcut(&self) -> bool {
+        if self.mode_stack.current() == NORMAL_MODE &&
+            self.operators.keys().any(|keys| keys.starts_with(self.shortcut.mapping_keys()))
+        {
+            return false;
+        }
+        self.current_mappings()
+            .map_or(true, |mappings| !self.shortcut.is_mapping_start(mappings))
+    }
+
+    /// Get the name of the operator corresponding to the current shortcut, which only exists in the normal mode.
+    fn operator(&self) -> Option<String> {
+        if self.mode_stack.current() != NORMAL_MODE {
+            return None;
+        }
+        self.operators.get(self.shortcut.mapping_keys()).cloned()
+    }
+
+    /// Wait for the motion of the operator corresponding to the current shortcut in the operator-pending mode.
+    fn start_operator(&mut self, name: String, actions: &mut Vec<Action>) {
+        let operator = PendingOperator {
+            count: self.shortcut.count(),
+            keys: self.shortcut.mapping_keys().to_vec(),
+            name,
+            typed_keys: vec![],
+        };
+        self.forget_shortcut(actions);
+        self.pending_operator = Some(operator);
+        if let Some(previous_mode) = self.mode_stack.push(OPERATOR_MODE) {
+            actions.push(ModeChanged(previous_mode));
+        }
+    }
+
+    /// Give the next fed key to the application or stop feeding keys when there is none.
+    /// The mappings expanded by a repetition of keys do not count for the next repetitions.
+    fn type_next_key(&mut self, actions: &mut Vec<Action>) {
+        loop {
+            match self.fed_keys.pop_front() {
+                Some(FedKey::Key(key, remap)) => {
+                    self.feeding_keys = true;
+                    self.fed_key_remap = remap;
+                    actions.push(TypeKey(key));
+                    return;
+                },
+                Some(FedKey::Repeat(keys, remap, count)) => {
+                    self.mapping_expansions = 0;
+                    if count > 1 {
+                        self.fed_keys.push_front(FedKey::Repeat(keys.clone(), remap, count - 1));
+                    }
+                    for key in keys.into_iter().rev() {
+                        self.fed_keys.push_front(FedKey::Key(key, remap));
+                    }
+                },
+                None => {
+                    self.feeding_keys = false;
+                    self.mapping_expansions = 0;
+                    return;
+                },
+            }
+        }
+    }
+
+    /// List the mappings of the current mode which start with the keys typed.
+    /// The count typed before the keys is ignored.
+    fn update_which_key(&mut self, actions: &mut Vec<Action>) {
+        let mut continuations: Vec<_> = {
+            let shortcut = self.shortcut.mapping_keys();
+            if shortcut.is_empty() {
+                return;
+            }
+            self.current_mappings()
+                .map(|mappings| {
+                    mappings.entries_starting_with(shortcut).into_iter()
+                        .filter(|&(ref keys, _)| !keys.is_empty())
+                        .map(|(keys, mapping)| (keys_to_string(&keys), mapping.action.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        continuations.sort();
+        self.which_key_shown = !continuations.is_empty();
+        actions.push(WhichKey(continuations));
+    }
+
+    /// Get the time to wait before showing the continuations of the keys typed.
+    /// When the keys are a mapping waiting for a longer mapping, they are shown before the mapping timeout runs
+    /// out.
+    fn which_key_delay(&self) -> u32 {
+        if self.ambiguous_action.is_some() {
+            self.which_key_timeout.min(self.mapping_timeout / 2)
+        }
+        else {
+            self.which_key_timeout
+        }
+    }
+
+    /// Update the continuations after a key was added to the shortcut.
+    /// When they are hidden, they are shown after the user stops typing for the which-key delay.
+    fn which_key_key_added(&mut self, actions: &mut Vec<Action>) {
+        let delay = self.which_key_delay();
+        if self.which_key_shown {
+            self.update_which_key(actions);
+        }
+        else if delay > 0 {
+            actions.push(StartTimer(Timer::WhichKey(self.shortcut_generation), delay));
+        }
+    }
+}
+
+/// Split the count typed before the keys.
+fn split_count(keys: &[Key]) -> (Option<u32>, &[Key]) {
+    let digits = keys.iter()
+        .take_while(|key| match **key {
+            Char(character) => character.is_digit(10),
+            _ => false,
+        })
+        .count();
+    let mut count = 0u32;
+    for key in &keys[..digits] {
+        if let Char(character) = *key {
+            count = count.saturating_mul(10).saturating_add(character.to_digit(10).unwrap_or_default());
+        }
+    }
+    // Like in the normal mode, 0 is not a count.
+    if digits == 0 || count == 0 {
+        (None, keys)
+    }
+    else {
+        (Some(count), &keys[digits..])
+    }
+}
+
+/// Multiply the count of the operator by the count of the motion.
+fn total_count(operator_count: Option<u32>, motion_count: Option<u32>) -> Option<u32> {
+    match (operator_count, motion_count) {
+        (Some(operator_count), Some(motion_count)) => Some(operator_count.saturating_mul(motion_count)),
+        (count, None) | (None, count) => count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use key::Key::{Char, Escape};
+    use super::{split_count, total_count};
+
+    #[test]
+    fn count_before_keys() {
+        assert_eq!(split_count(&[Char('3'), Char('w')]), (Some(3), &[Char('w')][..]));
+        assert_eq!(split_count(&[Char('1'), Char('2'), Escape]), (Some(12), &[Escape][..]));
+        assert_eq!(split_count(&[Char('w')]), (None, &[Char('w')][..]));
+        // 0 is a key, like in the normal mode.
+        assert_eq!(split_count(&[Char('0')]), (None, &[Char('0')][..]));
+        assert_eq!(split_count(&vec![Char('9'); 12]).0, Some(u32::max_value()));
+        assert_eq!(split_count(&[]), (None, &[][..]));
+    }
+
+    #[test]
+    fn multiplied_count() {
+        assert_eq!(total_count(Some(2), Some(3)), Some(6));
+        assert_eq!(total_count(Some(2), None), Some(2));
+        assert_eq!(total_count(None, Some(3)), Some(3));
+        assert_eq!(total_count(None, None), None);
+    }
+}
@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! The keys which can be typed in the mappings.
+
+use std::fmt::{self, Display, Formatter};
+
+use mg_settings::key::Key as SettingsKey;
+
+use self::Key::*;
+
+/// A key which can be used in a mapping.
+/// It has the keys of mg_settings and the keys mg_settings has no notation for: the function keys after F12, the
+/// keys known by their keysym, the mouse buttons, the scroll directions and the Super and Hyper modifiers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Key {
+    Alt(Box<Key>),
+    Backspace,
+    /// A mouse button (i.e. 8 for `<Button8>`).
+    Button(u32),
+    Char(char),
+    Control(Box<Key>),
+    Delete,
+    Down,
+    End,
+    Enter,
+    Escape,
+    /// A function key (i.e. 13 for `<F13>`).
+    F(u32),
+    Home,
+    Hyper(Box<Key>),
+    Insert,
+    /// A key which types no character, known by its GDK keysym (i.e. 0x1008ff13 for the raise volume key).
+    Keysym(u32),
+    Left,
+    PageDown,
+    PageUp,
+    Right,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+    ScrollUp,
+    Shift(Box<Key>),
+    Space,
+    Super(Box<Key>),
+    Tab,
+    Up,
+}
+
+impl Key {
+    /// Check if the key has no notation in mg_settings, with or without modifiers.
+    pub fn is_extended(&self) -> bool {
+        match *self {
+            Alt(ref key) | Control(ref key) | Shift(ref key) => key.is_extended(),
+            Button(_) | Hyper(_) | Keysym(_) | ScrollDown | ScrollLeft | ScrollRight | ScrollUp | Super(_) => true,
+            F(number) => number > 12,
+            _ => false,
+        }
+    }
+
+    /// Get the name of the key, as written between `<` and `>`.
+    pub fn name(&self) -> String {
+        let name =
+            match *self {
+                Alt(ref key) => return format!("A-{}", key.name()),
+                Button(button) => return format!("Button{}", button),
+                Char(character) => return character.to_string(),
+                Control(ref key) => return format!("C-{}", key.name()),
+                F(number) => return format!("F{}", number),
+                Hyper(ref key) => return format!("Hyper-{}", key.name()),
+                Keysym(keysym) => return format!("keysym:{:#x}", keysym),
+                Shift(ref key) => return format!("S-{}", key.name()),
+                Super(ref key) => return format!("Super-{}", key.name()),
+                Backspace => "Backspace",
+                Delete => "Delete",
+                Down => "Down",
+                End => "End",
+                Enter => "Enter",
+                Escape => "Esc",
+                Home => "Home",
+                Insert => "Insert",
+                Left => "Left",
+                PageDown => "PageDown",
+                PageUp => "PageUp",
+                Right => "Right",
+                ScrollDown => "ScrollDown",
+                ScrollLeft => "ScrollLeft",
+                ScrollRight => "ScrollRight",
+                ScrollUp => "ScrollUp",
+                Space => "Space",
+                Tab => "Tab",
+                Up => "Up",
+            };
+        name.to_string()
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            Char(character) => write!(formatter, "{}", character),
+            _ => write!(formatter, "<{}>", self.name()),
+        }
+    }
+}
+
+impl From<SettingsKey> for Key {
+    fn from(key: SettingsKey) -> Self {
+        match key {
+            SettingsKey::Alt(key) => Alt(Box::new(Key::from(*key))),
+            SettingsKey::Backspace => Backspace,
+            SettingsKey::Char(character) => Char(character),
+            SettingsKey::Control(key) => Control(Box::new(Key::from(*key))),
+            SettingsKey::Delete => Delete,
+            SettingsKey::Down => Down,
+            SettingsKey::End => End,
+            SettingsKey::Enter => Enter,
+            SettingsKey::Escape => Escape,
+            SettingsKey::F1 => F(1),
+            SettingsKey::F2 => F(2),
+            SettingsKey::F3 => F(3),
+            SettingsKey::F4 => F(4),
+            SettingsKey::F5 => F(5),
+            SettingsKey::F6 => F(6),
+            SettingsKey::F7 => F(7),
+            SettingsKey::F8 => F(8),
+            SettingsKey::F9 => F(9),
+            SettingsKey::F10 => F(10),
+            SettingsKey::F11 => F(11),
+            SettingsKey::F12 => F(12),
+            SettingsKey::Home => Home,
+            SettingsKey::Insert => Insert,
+            SettingsKey::Left => Left,
+            SettingsKey::PageDown => PageDown,
+            SettingsKey::PageUp => PageUp,
+            SettingsKey::Right => Right,
+            SettingsKey::Shift(key) => Shift(Box::new(Key::from(*key))),
+            SettingsKey::Space => Space,
+            SettingsKey::Tab => Tab,
+            SettingsKey::Up => Up,
+        }
+    }
+}
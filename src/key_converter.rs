@@ -19,24 +19,135 @@
  * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
  */
 
-use gdk::{EventKey, ModifierType};
+use gdk::{self, Event, EventButton, EventKey, EventScroll, EventType, Keymap, ModifierType, ScrollDirection, WindowExt};
 use gdk::keys::constants::{self as key, A,
     _0, _1, _2, _3, _4, _5, _6, _7, _8, _9, B, C, D, E, F, G, H, I, J, K, KP_0, KP_1, KP_2, KP_3,
     KP_4, KP_5, KP_6, KP_7, KP_8, KP_9, L, M, N, O, P, Q, R, Return, S, T, U, V, W, X, Y, Z, a, b,
     c, d, e, f, g, h, i, j, k, l, m, n, o, p, q, r, s, t, u, v, w, x, y, z};
-use mg_settings::key::Key::{self, Alt, Backspace, Char, Control, Delete, Down, End, Enter, Escape, F1, F2, F3, F4, F5,
-    F6, F7, F8, F9, F10, F11, F12, Home, Insert, Left, PageDown, PageUp, Right, Shift, Space, Tab, Up};
+use gdk_sys::GdkKeymapKey;
+use glib::translate::ToGlibPtr;
+use key::Key::{self, Alt, Backspace, Button, Char, Control, Delete, Down, End, Enter, Escape, Home, Hyper, Insert,
+    Keysym, Left, PageDown, PageUp, Right, ScrollDown, ScrollLeft, ScrollRight, ScrollUp, Shift, Space, Super, Tab,
+    Up};
+
+/// Convert a GDK mouse button press to an MG Key (i.e. `<Button8>`).
+/// The double and triple clicks are ignored since their first click was already converted.
+pub fn gdk_button_to_key(event: &EventButton) -> Option<Key> {
+    let button = event.get_button();
+    if event.get_event_type() != EventType::ButtonPress || button == 0 {
+        return None;
+    }
+    Some(add_modifiers(Button(button), event.get_state()))
+}
 
 /// Convert a GDK key to an MG Key.
 pub fn gdk_key_to_key(key: &EventKey) -> Option<Key> {
-    let alt_pressed = key.get_state().contains(ModifierType::MOD1_MASK);
-    let control_pressed = key.get_state().contains(ModifierType::CONTROL_MASK);
-    let shift_pressed = key.get_state().contains(ModifierType::SHIFT_MASK);
+    to_key(key)
+        .map(|converted_key| add_modifiers(converted_key, key.get_state()))
+}
+
+/// Convert a GDK key to the MG Key at the same position on a US QWERTY layout, using its hardware keycode.
+/// This allows typing the mappings with another layout (i.e. Cyrillic or AZERTY).
+/// The keycodes are the ones of X11 and Wayland, which are the Linux keycodes plus 8.
+pub fn gdk_keycode_to_key(key: &EventKey) -> Option<Key> {
+    let state = key.get_state();
+    let (character, shifted_character) = us_layout_chars(key.get_hardware_keycode())?;
+    let shift_pressed = state.contains(ModifierType::SHIFT_MASK);
+    let shifted =
+        if character.is_ascii_alphabetic() {
+            shift_pressed != state.contains(ModifierType::LOCK_MASK)
+        }
+        else {
+            shift_pressed
+        };
+    let character =
+        if shifted {
+            shifted_character
+        }
+        else {
+            character
+        };
+    Some(add_modifiers(Char(character), state))
+}
+
+/// Create the GDK key press or key release event of the key for the window, as if the user typed it.
+/// The event is marked as sent by the application to tell it apart from the keys typed by the user.
+/// Returns None for the mouse keys, which have no key event.
+pub fn key_to_gdk_key(key: &Key, window: &gdk::Window, event_type: EventType) -> Option<EventKey> {
+    let (keyval, mut state) = key_to_keyval(key)?;
+    let mut event = Event::new(event_type).downcast::<EventKey>().ok()?;
+    let display = window.get_display();
+    if let Some(keyboard) = display.get_default_seat().and_then(|seat| seat.get_keyboard()) {
+        event.set_device(Some(&keyboard));
+    }
+    // The key bindings of the widgets (i.e. <Home> in an entry) use the hardware keycode.
+    let mut keycode = 0;
+    let mut group = 0;
+    if let Some(entry) = Keymap::get_for_display(&display).and_then(|keymap| keymap.get_entries_for_keyval(keyval)
+        .into_iter().next())
+    {
+        let entry: *const GdkKeymapKey = entry.to_glib_none().0;
+        let entry = unsafe { &*entry };
+        keycode = entry.keycode as u16;
+        group = entry.group as u8;
+        // The characters on the second level of the layout are typed with shift.
+        if entry.level == 1 && is_char(key) {
+            state |= ModifierType::SHIFT_MASK;
+        }
+    }
+    {
+        let raw_event = event.as_mut();
+        raw_event.group = group;
+        raw_event.hardware_keycode = keycode;
+        raw_event.keyval = keyval;
+        raw_event.send_event = 1;
+        raw_event.state = state.bits();
+        raw_event.window = window.to_glib_full();
+    }
+    Some(event)
+}
+
+/// Convert a GDK scroll to an MG Key (i.e. `<ScrollUp>`).
+/// The smooth scrolls are converted to the direction in which they scroll the most.
+pub fn gdk_scroll_to_key(event: &EventScroll) -> Option<Key> {
     let key =
-        match to_key(key) {
-            Some(key) => key,
-            None => return None,
+        match event.get_direction() {
+            ScrollDirection::Down => ScrollDown,
+            ScrollDirection::Left => ScrollLeft,
+            ScrollDirection::Right => ScrollRight,
+            ScrollDirection::Smooth => {
+                let (delta_x, delta_y) = event.get_delta();
+                if delta_x == 0.0 && delta_y == 0.0 {
+                    return None;
+                }
+                else if delta_y.abs() >= delta_x.abs() && delta_y < 0.0 {
+                    ScrollUp
+                }
+                else if delta_y.abs() >= delta_x.abs() {
+                    ScrollDown
+                }
+                else if delta_x < 0.0 {
+                    ScrollLeft
+                }
+                else {
+                    ScrollRight
+                }
+            },
+            ScrollDirection::Up => ScrollUp,
+            _ => return None,
         };
+    Some(add_modifiers(key, event.get_state()))
+}
+
+/// Wrap the key in the modifiers which are pressed.
+/// Shift is not added to the characters since it already changed the character.
+fn add_modifiers(key: Key, state: ModifierType) -> Key {
+    let alt_pressed = state.contains(ModifierType::MOD1_MASK);
+    let control_pressed = state.contains(ModifierType::CONTROL_MASK);
+    let hyper_pressed = state.contains(ModifierType::HYPER_MASK);
+    let shift_pressed = state.contains(ModifierType::SHIFT_MASK);
+    // Super is usually reported as the Mod4 modifier on X11.
+    let super_pressed = state.intersects(ModifierType::SUPER_MASK | ModifierType::MOD4_MASK);
 
     let control_constructor: fn(Key) -> Key =
         if control_pressed {
@@ -62,7 +173,37 @@ pub fn gdk_key_to_key(key: &EventKey) -> Option<Key> {
             |key| key
         };
 
-    Some(control_constructor(alt_constructor(shift_constructor(key))))
+    let super_constructor: fn(Key) -> Key =
+        if super_pressed {
+            |key| Super(Box::new(key))
+        }
+        else {
+            |key| key
+        };
+
+    let hyper_constructor: fn(Key) -> Key =
+        if hyper_pressed {
+            |key| Hyper(Box::new(key))
+        }
+        else {
+            |key| key
+        };
+
+    control_constructor(alt_constructor(super_constructor(hyper_constructor(shift_constructor(key)))))
+}
+
+/// Get the keyval of the key and the modifiers pressed with it.
+fn key_to_keyval(key: &Key) -> Option<(u32, ModifierType)> {
+    let (key, modifier) =
+        match *key {
+            Alt(ref key) => (key, ModifierType::MOD1_MASK),
+            Control(ref key) => (key, ModifierType::CONTROL_MASK),
+            Hyper(ref key) => (key, ModifierType::HYPER_MASK),
+            Shift(ref key) => (key, ModifierType::SHIFT_MASK),
+            Super(ref key) => (key, ModifierType::SUPER_MASK),
+            _ => return unmodified_keyval(key).map(|keyval| (keyval, ModifierType::empty())),
+        };
+    key_to_keyval(key).map(|(keyval, state)| (keyval, state | modifier))
 }
 
 fn is_char(key: &Key) -> bool {
@@ -74,6 +215,13 @@ fn is_char(key: &Key) -> bool {
     }
 }
 
+/// Check if the keysym is a modifier or a lock key, which are not keys of the mappings.
+fn is_modifier_keysym(keysym: u32) -> bool {
+    // Shift_L to Hyper_R, the ISO modifiers and locks, Mode_switch and Num_Lock.
+    (keysym >= 0xFFE1 && keysym <= 0xFFEE) || (keysym >= 0xFE01 && keysym <= 0xFE13) || keysym == 0xFF7E ||
+        keysym == 0xFF7F
+}
+
 #[allow(non_upper_case_globals)]
 fn to_key(key: &EventKey) -> Option<Key> {
     let key =
@@ -157,55 +305,164 @@ fn to_key(key: &EventKey) -> Option<Key> {
             key::ccedilla => Char('ç'),
             key::Ccedilla => Char('Ç'),
             key::comma => Char(','),
-            key::Delete => Delete,
+            key::Delete | key::KP_Delete => Delete,
             key::dollar => Char('$'),
-            key::Down => Down,
+            key::Down | key::KP_Down => Down,
             key::eacute => Char('é'),
             key::Eacute => Char('É'),
-            key::End => End,
+            key::End | key::KP_End => End,
             key::egrave => Char('è'),
             key::Egrave => Char('È'),
             key::equal => Char('='),
             key::Escape => Escape,
             key::exclam => Char('!'),
-            key::F1 => F1,
-            key::F2 => F2,
-            key::F3 => F3,
-            key::F4 => F4,
-            key::F5 => F5,
-            key::F6 => F6,
-            key::F7 => F7,
-            key::F8 => F8,
-            key::F9 => F9,
-            key::F10 => F10,
-            key::F11 => F11,
-            key::F12 => F12,
-            key::Home => Home,
-            key::Insert => Insert,
-            key::ISO_Left_Tab | key::Tab => Tab,
-            key::Left => Left,
+            key::Home | key::KP_Home => Home,
+            key::Insert | key::KP_Insert => Insert,
+            key::ISO_Left_Tab | key::KP_Tab | key::Tab => Tab,
+            key::KP_Left | key::Left => Left,
             key::leftanglebracket => Char('<'),
             key::minus => Char('-'),
             key::multiply => Char('*'),
             key::numbersign => Char('#'),
-            key::Page_Down => PageDown,
-            key::Page_Up => PageUp,
+            key::KP_Page_Down | key::Page_Down => PageDown,
+            key::KP_Page_Up | key::Page_Up => PageUp,
             key::parenleft => Char('('),
             key::parenright => Char(')'),
             key::percent => Char('%'),
             key::period => Char('.'),
             key::plus => Char('+'),
-            Return => Enter,
-            key::Right => Right,
+            key::KP_Enter | Return => Enter,
+            key::KP_Right | key::Right => Right,
             key::rightanglebracket => Char('>'),
             key::question => Char('?'),
             key::quotedbl => Char('"'),
             key::semicolon => Char(';'),
             key::slash => Char('/'),
-            key::space => Space,
+            key::KP_Space | key::space => Space,
             key::underscore => Char('_'),
-            key::Up => Up,
-            _ => return None,
+            key::KP_Up | key::Up => Up,
+            keyval if *keyval >= *key::F1 && *keyval <= *key::F35 => Key::F(*keyval - *key::F1 + 1),
+            // The other keys are the character they type or, when they type no character, their keysym.
+            keyval =>
+                match keyval.to_unicode() {
+                    Some(character) if !character.is_control() => Char(character),
+                    _ if keyval == key::VoidSymbol || is_modifier_keysym(*keyval) => return None,
+                    _ => Keysym(*keyval),
+                },
         };
     Some(key)
 }
+
+/// Get the keyval of a key without modifier.
+fn unmodified_keyval(key: &Key) -> Option<u32> {
+    let keyval =
+        match *key {
+            Backspace => key::BackSpace,
+            Char(character) => return Some(gdk::unicode_to_keyval(character as u32)),
+            Delete => key::Delete,
+            Down => key::Down,
+            End => key::End,
+            Enter => Return,
+            Escape => key::Escape,
+            Key::F(number) => return Some(*key::F1 + number - 1),
+            Home => key::Home,
+            Insert => key::Insert,
+            Keysym(keysym) => return Some(keysym),
+            Left => key::Left,
+            PageDown => key::Page_Down,
+            PageUp => key::Page_Up,
+            Right => key::Right,
+            Space => key::space,
+            Tab => key::Tab,
+            Up => key::Up,
+            Alt(_) | Button(_) | Control(_) | Hyper(_) | ScrollDown | ScrollLeft | ScrollRight | ScrollUp | Shift(_) |
+                Super(_) => return None,
+        };
+    Some(*keyval)
+}
+
+/// Get the characters typed by the key with this hardware keycode on a US layout, without and with shift.
+fn us_layout_chars(keycode: u16) -> Option<(char, char)> {
+    let chars =
+        match keycode {
+            10 => ('1', '!'),
+            11 => ('2', '@'),
+            12 => ('3', '#'),
+            13 => ('4', '$'),
+            14 => ('5', '%'),
+            15 => ('6', '^'),
+            16 => ('7', '&'),
+            17 => ('8', '*'),
+            18 => ('9', '('),
+            19 => ('0', ')'),
+            20 => ('-', '_'),
+            21 => ('=', '+'),
+            24 => ('q', 'Q'),
+            25 => ('w', 'W'),
+            26 => ('e', 'E'),
+            27 => ('r', 'R'),
+            28 => ('t', 'T'),
+            29 => ('y', 'Y'),
+            30 => ('u', 'U'),
+            31 => ('i', 'I'),
+            32 => ('o', 'O'),
+            33 => ('p', 'P'),
+            34 => ('[', '{'),
+            35 => (']', '}'),
+            38 => ('a', 'A'),
+            39 => ('s', 'S'),
+            40 => ('d', 'D'),
+            41 => ('f', 'F'),
+            42 => ('g', 'G'),
+            43 => ('h', 'H'),
+            44 => ('j', 'J'),
+            45 => ('k', 'K'),
+            46 => ('l', 'L'),
+            47 => (';', ':'),
+            48 => ('\'', '"'),
+            49 => ('`', '~'),
+            51 => ('\\', '|'),
+            52 => ('z', 'Z'),
+            53 => ('x', 'X'),
+            54 => ('c', 'C'),
+            55 => ('v', 'V'),
+            56 => ('b', 'B'),
+            57 => ('n', 'N'),
+            58 => ('m', 'M'),
+            59 => (',', '<'),
+            60 => ('.', '>'),
+            61 => ('/', '?'),
+            _ => return None,
+        };
+    Some(chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::us_layout_chars;
+
+    #[test]
+    fn us_layout() {
+        assert_eq!(us_layout_chars(10), Some(('1', '!')));
+        assert_eq!(us_layout_chars(24), Some(('q', 'Q')));
+        assert_eq!(us_layout_chars(38), Some(('a', 'A')));
+        assert_eq!(us_layout_chars(61), Some(('/', '?')));
+        // The function keys and the keys which are not printable are not handled.
+        assert_eq!(us_layout_chars(9), None);
+        assert_eq!(us_layout_chars(67), None);
+        assert_eq!(us_layout_chars(0), None);
+    }
+
+    #[test]
+    fn us_layout_is_complete() {
+        let chars: Vec<_> = (0..256).filter_map(us_layout_chars).collect();
+        for character in "abcdefghijklmnopqrstuvwxyz0123456789".chars() {
+            assert!(chars.iter().any(|&(unshifted, _)| unshifted == character), "{}", character);
+        }
+        for &(unshifted, shifted) in &chars {
+            if unshifted.is_ascii_alphabetic() {
+                assert_eq!(unshifted.to_ascii_uppercase(), shifted);
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+//! Parser for the key notation of the mappings (i.e. `gg<C-a><Enter>`).
+
+use mg_settings::errors::{Error, Result};
+
+use key::Key::{self, Alt, Backspace, Button, Char, Control, Delete, Down, End, Enter, Escape, F, Home, Hyper, Insert,
+    Keysym, Left, PageDown, PageUp, Right, ScrollDown, ScrollLeft, ScrollRight, ScrollUp, Shift, Space, Super, Tab,
+    Up};
+
+/// The number of function keys which have a keysym.
+const FUNCTION_KEY_COUNT: u32 = 35;
+
+/// Get the key of a mouse button or of a scroll direction from its lowercase name.
+fn mouse_key(name: &str) -> Option<Key> {
+    let key =
+        match name {
+            "scrolldown" => ScrollDown,
+            "scrollleft" => ScrollLeft,
+            "scrollright" => ScrollRight,
+            "scrollup" => ScrollUp,
+            _ if name.starts_with("button") => {
+                let button: u32 = name["button".len()..].parse().ok()?;
+                if button == 0 {
+                    return None;
+                }
+                Button(button)
+            },
+            _ => return None,
+        };
+    Some(key)
+}
+
+/// Parse a sequence of keys.
+/// A `<` always starts a key name (i.e. `<Esc>` or `<C-a>`), so the `<` key itself is written `<lt>`.
+pub fn parse_keys(input: &str) -> Result<Vec<Key>> {
+    let mut keys = vec![];
+    let mut rest = input;
+    while let Some(character) = rest.chars().next() {
+        if character == '<' {
+            let end = rest.find('>')
+                .ok_or_else(|| Error::Msg(format!("Expecting > after {} in {}", rest, input)))?;
+            let name = &rest[1..end];
+            let key = parse_special_key(name)
+                .ok_or_else(|| Error::Msg(format!("Unknown key <{}> in {}", name, input)))?;
+            keys.push(key);
+            rest = &rest[end + 1..];
+        }
+        else {
+            keys.push(char_to_key(character));
+            rest = &rest[character.len_utf8()..];
+        }
+    }
+    Ok(keys)
+}
+
+fn char_to_key(character: char) -> Key {
+    if character == ' ' {
+        Space
+    }
+    else {
+        Char(character)
+    }
+}
+
+/// Parse the name of a key between `<` and `>`, with its modifiers (i.e. `C-A-x` or `Super-x`).
+fn parse_special_key(name: &str) -> Option<Key> {
+    let mut alt = false;
+    let mut control = false;
+    let mut hyper = false;
+    let mut shift = false;
+    let mut super_key = false;
+    let mut name = name;
+    loop {
+        let lowercase_name = name.to_lowercase();
+        if lowercase_name.len() > "super-".len() && lowercase_name.starts_with("super-") {
+            super_key = true;
+            name = &name["super-".len()..];
+            continue;
+        }
+        if lowercase_name.len() > "hyper-".len() && lowercase_name.starts_with("hyper-") {
+            hyper = true;
+            name = &name["hyper-".len()..];
+            continue;
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(modifier), Some('-'), Some(_)) => {
+                match modifier {
+                    'A' | 'a' | 'M' | 'm' => alt = true,
+                    'C' | 'c' => control = true,
+                    'S' | 's' => shift = true,
+                    _ => return None,
+                }
+                name = &name[modifier.len_utf8() + 1..];
+            },
+            _ => break,
+        }
+    }
+    let has_modifier = alt || control || hyper || shift || super_key;
+    let mut chars = name.chars();
+    let mut key =
+        match (chars.next(), chars.next()) {
+            // A single character is only a key name when it has a modifier: <a> is 3 characters.
+            (Some(character), None) if has_modifier => {
+                if shift {
+                    shift = false;
+                    Char(character.to_uppercase().next().unwrap_or(character))
+                }
+                else {
+                    char_to_key(character)
+                }
+            },
+            _ => named_key(&name.to_lowercase())?,
+        };
+    // Same nesting as the keys converted from the GDK events.
+    if shift {
+        key = Shift(Box::new(key));
+    }
+    if hyper {
+        key = Hyper(Box::new(key));
+    }
+    if super_key {
+        key = Super(Box::new(key));
+    }
+    if alt {
+        key = Alt(Box::new(key));
+    }
+    if control {
+        key = Control(Box::new(key));
+    }
+    Some(key)
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let key =
+        match name {
+            "bs" | "backspace" => Backspace,
+            "cr" | "enter" | "return" => Enter,
+            "del" | "delete" => Delete,
+            "down" => Down,
+            "end" => End,
+            "esc" | "escape" => Escape,
+            "home" => Home,
+            "insert" => Insert,
+            "left" => Left,
+            "lt" => Char('<'),
+            "pagedown" => PageDown,
+            "pageup" => PageUp,
+            "right" => Right,
+            "space" => Space,
+            "tab" => Tab,
+            "up" => Up,
+            _ if name.starts_with("keysym:") => Keysym(parse_keysym(&name["keysym:".len()..])?),
+            _ if name.starts_with('f') => {
+                let number: u32 = name[1..].parse().ok()?;
+                if number == 0 || number > FUNCTION_KEY_COUNT {
+                    return None;
+                }
+                F(number)
+            },
+            _ => return mouse_key(name),
+        };
+    Some(key)
+}
+
+/// Parse a keysym written in hexadecimal (i.e. `0x1008ff13`) or in decimal.
+fn parse_keysym(keysym: &str) -> Option<u32> {
+    if keysym.starts_with("0x") {
+        u32::from_str_radix(&keysym[2..], 16).ok()
+    }
+    else {
+        keysym.parse().ok()
+    }
+}
+
+/// Convert a sequence of keys to the notation accepted by `parse_keys()`.
+pub fn keys_to_string(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| {
+            match *key {
+                Char('<') => "<lt>".to_string(),
+                Space => " ".to_string(),
+                _ => key.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use key::Key::{Alt, Button, Char, Control, Enter, Escape, F, Hyper, Keysym, ScrollUp, Shift, Space, Super, Tab};
+    use super::{keys_to_string, parse_keys};
+
+    #[test]
+    fn parse_extended_keys() {
+        assert_eq!(parse_keys("<Button8>").ok(), Some(vec![Button(8)]));
+        assert_eq!(parse_keys("<C-ScrollUp>").ok(), Some(vec![Control(Box::new(ScrollUp))]));
+        assert_eq!(parse_keys("<F13>").ok(), Some(vec![F(13)]));
+        assert_eq!(parse_keys("<keysym:0x1008ff13>").ok(), Some(vec![Keysym(0x1008ff13)]));
+        assert_eq!(parse_keys("<Super-h>").ok(), Some(vec![Super(Box::new(Char('h')))]));
+        assert_eq!(parse_keys("<C-Hyper-S-Tab>").ok(),
+            Some(vec![Control(Box::new(Hyper(Box::new(Shift(Box::new(Tab))))))]));
+    }
+
+    #[test]
+    fn parse_invalid_keys() {
+        assert!(parse_keys("<a>").is_err());
+        assert!(parse_keys("<Foo>").is_err());
+        assert!(parse_keys("<F0>").is_err());
+        assert!(parse_keys("<Button0>").is_err());
+        assert!(parse_keys("<X-a>").is_err());
+        assert!(parse_keys("a<Esc").is_err());
+        assert!(parse_keys("<").is_err());
+    }
+
+    #[test]
+    fn parse_normal_keys() {
+        assert_eq!(parse_keys("g<Esc>").ok(), Some(vec![Char('g'), Escape]));
+        assert_eq!(parse_keys("<A-x> <CR>").ok(), Some(vec![Alt(Box::new(Char('x'))), Space, Enter]));
+        assert_eq!(parse_keys("<S-a>").ok(), Some(vec![Char('A')]));
+        assert_eq!(parse_keys("a>b<lt>").ok(), Some(vec![Char('a'), Char('>'), Char('b'), Char('<')]));
+        assert_eq!(parse_keys("").ok(), Some(vec![]));
+    }
+
+    #[test]
+    fn round_trip() {
+        for input in &["gg<C-a><Enter>", "<lt>", "<Super-h>", "<Hyper-x>", "<keysym:0x1008ff13>", "<Button8>",
+            "<C-ScrollUp>", "<F35>", "a b"]
+        {
+            assert_eq!(parse_keys(input).map(|keys| keys_to_string(&keys)).ok().as_ref().map(String::as_str),
+                Some(*input));
+        }
+    }
+}
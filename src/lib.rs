@@ -34,7 +34,6 @@
  * TODO: set the size of the status bar according to the size of the font.
  * TODO: different event for activate event of special commands.
  * TODO: use the gtk::Statusbar widget?
- * TODO: Associate a color with modes.
  */
 
 //! Minimal UI library based on GTK+.
@@ -48,11 +47,11 @@
 )]
 
 extern crate gdk;
+extern crate gdk_sys;
 extern crate glib;
 extern crate gtk;
 #[macro_use]
 extern crate log;
-#[macro_use]
 extern crate mg_settings;
 extern crate pango;
 #[macro_use]
@@ -62,38 +61,127 @@ extern crate relm_derive;
 
 mod app;
 pub mod completion;
+pub mod engine;
 mod file;
+pub mod key;
 mod key_converter;
+mod key_parser;
 
 /// List of modes
 pub type Modes = &'static [Mode];
 
 /// A mode contains a prefix (i.e. "i") and a name (i.e. "insert").
-/// It can only specify whether a count can be shown for it.
+/// It can specify whether a count can be shown for it, how the status bar looks in this mode and what happens to
+/// the keys typed in this mode.
 #[derive(Clone)]
 pub struct Mode {
+    /// The background color of the status bar in this mode (i.e. "green" or "#2e8b57").
+    pub color: Option<&'static str>,
+    /// The CSS class added to the status bar in this mode.
+    pub css_class: Option<&'static str>,
+    /// Whether the keys typed in this mode reach the widget which has the focus (ignored for the modes of mg).
+    pub key_policy: KeyPolicy,
+    /// The text shown in the status bar in this mode.
+    /// The name is shown when there is no label, except for the modes of mg.
+    pub label: Option<&'static str>,
     pub name: &'static str,
     pub prefix: &'static str,
     pub show_count: bool,
 }
 
-pub use app::{Mg, parse_config};
+impl Mode {
+    /// Create a mode with the default status bar and key policy.
+    /// Like the following methods, except `key_policy()`, it can be used in a static.
+    pub const fn new(name: &'static str, prefix: &'static str, show_count: bool) -> Self {
+        Self::with_key_policy(name, prefix, show_count, KeyPolicy::PassUnmapped)
+    }
+
+    /// Create a mode with the default status bar and the specified key policy.
+    pub const fn with_key_policy(name: &'static str, prefix: &'static str, show_count: bool, key_policy: KeyPolicy)
+        -> Self
+    {
+        Mode {
+            color: None,
+            css_class: None,
+            key_policy,
+            label: None,
+            name,
+            prefix,
+            show_count,
+        }
+    }
+
+    /// Set the background color of the status bar in this mode.
+    pub const fn color(mut self, color: &'static str) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Set the CSS class added to the status bar in this mode.
+    pub const fn css_class(mut self, css_class: &'static str) -> Self {
+        self.css_class = Some(css_class);
+        self
+    }
+
+    /// Set what happens to the keys typed in this mode.
+    /// Since the previous policy is dropped, it cannot be used in a static: use `with_key_policy()` instead.
+    pub fn key_policy(mut self, key_policy: KeyPolicy) -> Self {
+        self.key_policy = key_policy;
+        self
+    }
+
+    /// Set the text shown in the status bar in this mode.
+    pub const fn label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+}
+
+pub use app::{
+    BuiltinCommand,
+    ConfigCommand,
+    ConfigResult,
+    HookEvent,
+    KeyPolicy,
+    MappingDescription,
+    MappingSource,
+    Mg,
+    Operation,
+    parse_config,
+};
 pub use app::Msg::{
+    AddMode,
     Alert,
     AppClose,
     CloseWin,
+    CommandsWithCount,
     Completers,
     CompletionViewChange,
     CustomCommand,
+    CustomCommandWithCount,
     CustomDialog,
     DarkTheme,
     DeleteCompletionItem,
     Error,
+    FeedKeys,
+    GetMappings,
+    GetRegister,
+    HistoryFile,
     Info,
+    Mappings,
     Message,
     ModeChanged,
+    Motions,
+    OperatorCommand,
+    Operators,
+    PopMode,
+    PushMode,
     Question,
+    Register,
+    RegisterChanged,
+    RegistersFile,
     SetMode,
+    SetRegister,
     SetSetting,
     SettingChanged,
     StatusBarVisible,
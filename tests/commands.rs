@@ -21,8 +21,8 @@
 
 #![feature(use_extern_macros)]
 
+extern crate glib;
 extern crate gtk;
-extern crate libxdo;
 extern crate mg;
 extern crate mg_settings;
 #[macro_use]
@@ -32,14 +32,13 @@ extern crate relm;
 #[macro_use]
 extern crate relm_derive;
 
-mod utils;
-
-use std::thread;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use gtk::{LabelExt, WidgetExt};
-use libxdo::XDo;
 use mg::{
     CustomCommand,
+    FeedKeys,
     Mg,
 };
 use relm::{Widget, init_test};
@@ -47,7 +46,6 @@ use relm_derive::widget;
 
 use self::AppCommand::*;
 use self::Msg::*;
-use utils::XDoExt;
 
 #[derive(Commands)]
 pub enum AppCommand {
@@ -62,6 +60,8 @@ pub struct AppSettings {
 }
 
 pub struct Model {
+    /// The text shown by the last show command, checked after the main loop ends.
+    shown_text: Rc<RefCell<Option<String>>>,
     text: String,
 }
 
@@ -72,8 +72,13 @@ pub enum Msg {
 
 #[widget]
 impl Widget for Win {
-    fn model() -> Model {
+    fn init_view(&mut self) {
+        self.mg.emit(FeedKeys(":show test<Enter>:quit<Enter>".to_string()));
+    }
+
+    fn model(shown_text: Rc<RefCell<Option<String>>>) -> Model {
         Model {
+            shown_text,
             text: "Label".to_string(),
         }
     }
@@ -82,7 +87,10 @@ impl Widget for Win {
         match event {
             Command(command) => {
                 match command {
-                    Show(text) => self.model.text = format!("Showing text: {}", text),
+                    Show(text) => {
+                        self.model.text = format!("Showing text: {}", text);
+                        *self.model.shown_text.borrow_mut() = Some(text);
+                    },
                     Quit => gtk::main_quit(),
                 }
             },
@@ -105,20 +113,15 @@ impl Widget for Win {
 fn test_basic_command() {
     gtk::init().unwrap();
 
-    let win = init_test::<Win>(()).unwrap();
+    let shown_text = Rc::new(RefCell::new(None));
+    let _win = init_test::<Win>(shown_text.clone()).unwrap();
 
-    // TODO: send a message with a Sender to fetch the text?
-    //assert_eq!(Some("Label".to_string()), win.widget().label.get_text());
-
-    thread::spawn(|| {
-        // FIXME: this doesn't work.
-        let xdo = XDo::new(None).unwrap();
-        xdo.enter_command("show test");
-        xdo.enter_command("quit");
+    // Stop the main loop if the window is not closed by :quit, so that the test fails instead of blocking.
+    glib::timeout_add_seconds_local(10, || {
+        gtk::main_quit();
+        glib::Continue(false)
     });
-
     gtk::main();
 
-    // TODO: send a message with a Sender to fetch the text?
-    //assert_eq!(Some("Showing text: test".to_string()), win.widget().label.get_text());
+    assert_eq!(Some("test".to_string()), *shown_text.borrow());
 }
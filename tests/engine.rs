@@ -0,0 +1,408 @@
+/*
+ * Copyright (c) 2017 Boucher, Antoni <bouanto@zoho.com>
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in
+ * the Software without restriction, including without limitation the rights to
+ * use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+ * the Software, and to permit persons to whom the Software is furnished to do so,
+ * subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+ * FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+ * COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+ * CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+ */
+
+extern crate mg;
+extern crate mg_settings;
+
+use std::collections::HashMap;
+
+use mg::engine::{
+    Action,
+    Engine,
+    KeyTrie,
+    Mapping,
+    MappingSource,
+    ModeStack,
+    Operation,
+    Shortcut,
+    Timer,
+    NORMAL_MODE,
+    OPERATOR_MODE,
+    action_to_command,
+};
+use mg::engine::Action::{CompleteCommand, EnterCommandMode, EnterSpecialCommandMode, ModeChanged, Reset, StartTimer,
+    TypeKey, WhichKey};
+use mg::engine::ShortcutCommand::{Complete, Incomplete};
+use mg::key::Key::{self, Char, Control, Escape};
+
+fn shortcut(keys: &[Key]) -> Shortcut {
+    let mut shortcut = Shortcut::new();
+    for key in keys {
+        shortcut.push(key.clone());
+    }
+    shortcut
+}
+
+fn command(command: &str, count: Option<u32>, keys_len: usize) -> Action {
+    Action::Command {
+        command: command.to_string(),
+        count,
+        keys_len,
+    }
+}
+
+/// Create an engine without timers, with the mappings of the normal mode.
+fn engine(mappings: &[(&str, &str, bool)]) -> Engine {
+    let mut engine = Engine::new(|character| character == '/');
+    engine.set_command_names(["first", "open", "top"].iter().map(|name| name.to_string()).collect());
+    engine.set_which_key_timeout(0);
+    for &(keys, action, recursive) in mappings {
+        let keys = keys.chars().map(Char).collect();
+        let mapping = Mapping::new(action.to_string(), false, recursive, MappingSource::Config);
+        engine.add_mapping(NORMAL_MODE, keys, mapping);
+    }
+    engine
+}
+
+fn keys(engine: &mut Engine, keys: &str) -> Vec<Action> {
+    keys.chars().flat_map(|key| engine.key(Char(key))).collect()
+}
+
+/// Get the timer started by the actions.
+fn started_timer(actions: &[Action]) -> Option<Timer> {
+    actions.iter()
+        .filter_map(|action| match *action {
+            StartTimer(timer, _) => Some(timer),
+            _ => None,
+        })
+        .next()
+}
+
+fn mappings() -> KeyTrie<&'static str> {
+    let mut mappings = KeyTrie::new();
+    mappings.insert(vec![Char('g')], "first");
+    mappings.insert(vec![Char('g'), Char('g')], "top");
+    mappings.insert(vec![Control(Box::new(Char('w'))), Char('q')], "quit");
+    mappings
+}
+
+#[test]
+fn test_action_to_command() {
+    assert_eq!(Complete("quit".to_string()), action_to_command("quit"));
+    assert_eq!(Complete("open foo".to_string()), action_to_command(":open foo<Enter>"));
+    assert_eq!(Incomplete("open ".to_string()), action_to_command(":open "));
+}
+
+#[test]
+fn test_count() {
+    assert_eq!(None, shortcut(&[Char('g'), Char('g')]).count());
+    assert_eq!(Some(3), shortcut(&[Char('3'), Char('g')]).count());
+    assert_eq!(Some(42), shortcut(&[Char('4'), Char('2')]).count());
+    assert_eq!(Some(0), shortcut(&[Char('0')]).count());
+    assert_eq!(Some(u32::max_value()), shortcut(&vec![Char('9'); 12]).count());
+}
+
+#[test]
+fn test_mapping_keys() {
+    assert_eq!(&[Char('g'), Char('g')], shortcut(&[Char('1'), Char('2'), Char('g'), Char('g')]).mapping_keys());
+    assert!(shortcut(&[Char('1'), Char('2')]).mapping_keys().is_empty());
+    // A 0 typed first is a key, not a count.
+    assert_eq!(&[Char('0')], shortcut(&[Char('0')]).mapping_keys());
+    assert_eq!(&[Char('g'), Char('2')], shortcut(&[Char('g'), Char('2')]).mapping_keys());
+}
+
+#[test]
+fn test_mapping_resolution() {
+    let mappings = mappings();
+
+    let keys = shortcut(&[Char('2'), Char('g')]);
+    assert_eq!(Some(&"first"), keys.mapping(&mappings));
+    assert!(keys.has_longer_mapping(&mappings));
+    assert!(keys.is_mapping_start(&mappings));
+
+    let keys = shortcut(&[Char('g'), Char('g')]);
+    assert_eq!(Some(&"top"), keys.mapping(&mappings));
+    assert!(!keys.has_longer_mapping(&mappings));
+
+    let keys = shortcut(&[Control(Box::new(Char('w')))]);
+    assert_eq!(None, keys.mapping(&mappings));
+    assert!(keys.is_mapping_start(&mappings));
+
+    let keys = shortcut(&[Char('x')]);
+    assert_eq!(None, keys.mapping(&mappings));
+    assert!(!keys.is_mapping_start(&mappings));
+}
+
+#[test]
+fn test_shortcut_buffer() {
+    let mut keys = shortcut(&[Char('3'), Char('g')]);
+    assert_eq!(Some(Char('g')), keys.pop());
+    assert_eq!(&[Char('3')], keys.keys());
+    keys.clear();
+    assert!(keys.is_empty());
+    assert_eq!(None, keys.count());
+}
+
+#[test]
+fn test_key_trie() {
+    let mut mappings = mappings();
+    assert_eq!(Some(&"top"), mappings.get(&[Char('g'), Char('g')]));
+    assert_eq!(None, mappings.get(&[Control(Box::new(Char('w')))]));
+    assert_eq!(None, mappings.get(&[Char('x')]));
+
+    assert!(mappings.has_keys_starting_with(&[Control(Box::new(Char('w')))]));
+    assert!(mappings.has_keys_starting_with(&[Char('g'), Char('g')]));
+    assert!(!mappings.has_keys_starting_with(&[Char('g'), Char('x')]));
+    assert!(mappings.has_longer_keys(&[Char('g')]));
+    assert!(!mappings.has_longer_keys(&[Char('g'), Char('g')]));
+
+    assert_eq!(2, mappings.first_keys().count());
+
+    let mut continuations = mappings.entries_starting_with(&[Char('g')]);
+    continuations.sort_by_key(|&(ref keys, _)| keys.len());
+    assert_eq!(vec![(vec![], &"first"), (vec![Char('g')], &"top")], continuations);
+    assert_eq!(3, mappings.entries().len());
+
+    assert_eq!(Some("first"), mappings.insert(vec![Char('g')], "other"));
+    assert_eq!(Some(&"other"), mappings.get(&[Char('g')]));
+
+    assert_eq!(Some("top"), mappings.remove(&[Char('g'), Char('g')]));
+    assert_eq!(None, mappings.remove(&[Char('g'), Char('g')]));
+    assert!(!mappings.has_longer_keys(&[Char('g')]));
+    assert_eq!(Some("quit"), mappings.remove(&[Control(Box::new(Char('w'))), Char('q')]));
+    // The keys which do not lead to a mapping anymore are removed.
+    assert!(!mappings.has_keys_starting_with(&[Control(Box::new(Char('w')))]));
+    assert_eq!(Some("other"), mappings.remove(&[Char('g')]));
+    assert!(mappings.is_empty());
+    assert!(!mappings.has_keys_starting_with(&[]));
+}
+
+#[test]
+fn test_mode_stack() {
+    let mut modes = ModeStack::new("normal");
+    assert_eq!("normal", modes.current());
+
+    assert_eq!(Some("normal".to_string()), modes.push("insert"));
+    assert_eq!(None, modes.push("insert"));
+    assert_eq!(Some("insert".to_string()), modes.push("command"));
+    assert_eq!("command", modes.current());
+
+    assert_eq!("command", modes.pop());
+    assert_eq!("insert", modes.current());
+    assert_eq!("insert", modes.pop());
+    assert_eq!("normal", modes.current());
+    // There is no mode to go back to, so the base mode is entered.
+    assert_eq!("normal", modes.pop());
+    assert_eq!("normal", modes.current());
+
+    modes.push("insert");
+    assert_eq!("insert", modes.set("visual"));
+    assert_eq!("visual", modes.pop());
+    assert_eq!("normal", modes.current());
+
+    modes.push("insert");
+    modes.push("command");
+    assert_eq!("command", modes.replace("visual"));
+    assert_eq!("visual", modes.pop());
+    assert_eq!("normal", modes.current());
+}
+
+#[test]
+fn test_engine_command() {
+    let mut engine = engine(&[("gg", "top", true), ("o", ":open ", true)]);
+    assert_eq!(Vec::<Action>::new(), engine.key(Char('g')));
+    assert_eq!(&[Char('g')], engine.shortcut().keys());
+    assert_eq!(vec![Reset, command("top", None, 2)], engine.key(Char('g')));
+    assert!(engine.shortcut().is_empty());
+
+    assert_eq!(vec![Reset, command("top", Some(12), 4)], keys(&mut engine, "12gg"));
+    assert_eq!(vec![Reset, CompleteCommand("open ".to_string(), Some(3))], keys(&mut engine, "3o"));
+
+    // The keys which cannot become a mapping are forgotten.
+    assert_eq!(vec![Reset], keys(&mut engine, "gx"));
+    assert!(engine.shortcut().is_empty());
+
+    assert_eq!(vec![EnterCommandMode], engine.key(Char(':')));
+    assert_eq!(vec![EnterSpecialCommandMode('/')], engine.key(Char('/')));
+}
+
+#[test]
+fn test_engine_ambiguous_mapping() {
+    let mut engine = engine(&[("g", "first", true), ("gg", "top", true)]);
+    engine.set_mapping_timeout(1000);
+
+    let actions = engine.key(Char('g'));
+    let timer = started_timer(&actions).expect("mapping timer");
+    assert_eq!(vec![StartTimer(timer, 1000)], actions);
+    assert_eq!(vec![Reset, command("first", None, 1)], engine.timeout(timer));
+
+    // The timer of the previous keys does nothing.
+    let timer = started_timer(&engine.key(Char('g'))).expect("mapping timer");
+    assert_eq!(vec![Reset, command("top", None, 2)], engine.key(Char('g')));
+    assert_eq!(Vec::<Action>::new(), engine.timeout(timer));
+
+    // A key which does not continue the longer mapping executes the shorter one and starts a new shortcut.
+    engine.key(Char('g'));
+    assert_eq!(vec![Reset, command("first", None, 1), Reset], engine.key(Char('x')));
+
+    // Without timeout, the shorter mapping is executed right away.
+    engine.set_mapping_timeout(0);
+    assert_eq!(vec![Reset, command("first", None, 1)], engine.key(Char('g')));
+}
+
+#[test]
+fn test_engine_fed_keys() {
+    let mut engine = engine(&[("gg", "top", true), ("j", "gg", true)]);
+    assert_eq!(vec![Reset, TypeKey(Char('g'))], engine.key(Char('j')));
+    assert_eq!(Vec::<Action>::new(), engine.fed_key(Char('g')));
+    assert_eq!(vec![TypeKey(Char('g'))], engine.next_fed_key());
+    assert_eq!(vec![Reset, command("top", None, 2)], engine.fed_key(Char('g')));
+    assert_eq!(Vec::<Action>::new(), engine.next_fed_key());
+
+    // The typed keys are fed after the keys which are still being fed.
+    assert_eq!(vec![TypeKey(Char('g'))], engine.type_keys(vec![Char('g'), Char('g')]));
+    assert_eq!(Vec::<Action>::new(), engine.type_keys(vec![Char(':')]));
+    engine.fed_key(Char('g'));
+    assert_eq!(vec![TypeKey(Char('g'))], engine.next_fed_key());
+    engine.fed_key(Char('g'));
+    assert_eq!(vec![TypeKey(Char(':'))], engine.next_fed_key());
+    assert_eq!(vec![EnterCommandMode], engine.fed_key(Char(':')));
+    assert_eq!(Vec::<Action>::new(), engine.next_fed_key());
+}
+
+#[test]
+fn test_engine_repeated_fed_keys() {
+    let mut engine = engine(&[]);
+    assert_eq!(vec![TypeKey(Char('a'))], engine.feed_keys(vec![Char('a')], 2, true));
+    // The repetitions are typed before the keys which were waiting to be fed.
+    assert_eq!(Vec::<Action>::new(), engine.type_keys(vec![Char(':')]));
+    engine.fed_key(Char('a'));
+    assert_eq!(vec![TypeKey(Char('a'))], engine.next_fed_key());
+    engine.fed_key(Char('a'));
+    assert_eq!(vec![TypeKey(Char(':'))], engine.next_fed_key());
+}
+
+#[test]
+fn test_engine_many_repeated_fed_keys() {
+    let mut engine = engine(&[("gg", "top", true), ("j", "gg", true)]);
+    // The repetitions are added one at a time, so a large count does not allocate all the keys.
+    assert_eq!(vec![TypeKey(Char('j'))], engine.feed_keys(vec![Char('j'), Char('x')], u32::max_value(), true));
+    for _ in 0..2000 {
+        assert_eq!(vec![Reset], engine.fed_key(Char('j')));
+        assert_eq!(vec![TypeKey(Char('g'))], engine.next_fed_key());
+        engine.fed_key(Char('g'));
+        assert_eq!(vec![TypeKey(Char('g'))], engine.next_fed_key());
+        assert_eq!(vec![Reset, command("top", None, 2)], engine.fed_key(Char('g')));
+        assert_eq!(vec![TypeKey(Char('x'))], engine.next_fed_key());
+        engine.fed_key(Char('x'));
+        assert_eq!(vec![TypeKey(Char('j'))], engine.next_fed_key());
+    }
+}
+
+#[test]
+fn test_engine_recursive_mapping() {
+    let mut engine = engine(&[("r", "r", true)]);
+    let mut actions = engine.key(Char('r'));
+    for _ in 0..2000 {
+        if actions.contains(&Action::Error("Recursive mapping".to_string())) {
+            return;
+        }
+        actions = engine.fed_key(Char('r'));
+        actions.extend(engine.next_fed_key());
+    }
+    panic!("the recursive mapping did not stop");
+}
+
+#[test]
+fn test_engine_operator() {
+    let mut engine = engine(&[]);
+    let mut operators = HashMap::new();
+    operators.insert(vec![Char('d')], "delete".to_string());
+    engine.set_operators(operators);
+    let mut motions = HashMap::new();
+    motions.insert(vec![Char('w')], "word".to_string());
+    engine.set_motions(motions);
+
+    assert_eq!(vec![ModeChanged(NORMAL_MODE.to_string())], keys(&mut engine, "2d"));
+    assert_eq!(OPERATOR_MODE, engine.current_mode());
+    engine.key(Char('3'));
+    assert_eq!(vec![Char('d'), Char('3')], engine.shown_keys());
+    let operation = Operation {
+        count: Some(6),
+        motion: Some("word".to_string()),
+        operator: "delete".to_string(),
+    };
+    assert_eq!(vec![ModeChanged(OPERATOR_MODE.to_string()), Action::Operation(operation)], engine.key(Char('w')));
+    assert_eq!(NORMAL_MODE, engine.current_mode());
+
+    let operation = Operation {
+        count: None,
+        motion: None,
+        operator: "delete".to_string(),
+    };
+    assert_eq!(Some(&Action::Operation(operation)), keys(&mut engine, "dd").last());
+
+    engine.key(Char('d'));
+    assert_eq!(vec![ModeChanged(OPERATOR_MODE.to_string())], engine.key(Escape));
+    engine.key(Char('d'));
+    assert_eq!(vec![ModeChanged(OPERATOR_MODE.to_string()), Action::Error("Unknown motion: x".to_string())],
+        engine.key(Char('x')));
+    assert_eq!(NORMAL_MODE, engine.current_mode());
+
+    // The mappings starting with an operator are not shadowed by it.
+    engine.add_mapping(NORMAL_MODE, vec![Char('d'), Char('h')], Mapping::new("first".to_string(), false, true,
+        MappingSource::Config));
+    assert_eq!(Vec::<Action>::new(), engine.key(Char('d')));
+    assert_eq!(NORMAL_MODE, engine.current_mode());
+    assert_eq!(Some(&command("first", None, 2)), keys(&mut engine, "h").last());
+    let operation = Operation {
+        count: None,
+        motion: Some("word".to_string()),
+        operator: "delete".to_string(),
+    };
+    assert_eq!(vec![ModeChanged(NORMAL_MODE.to_string()), ModeChanged(OPERATOR_MODE.to_string()),
+        Action::Operation(operation)], keys(&mut engine, "dw"));
+}
+
+#[test]
+fn test_engine_which_key() {
+    let mut engine = engine(&[("gg", "top", true), ("gt", "open foo", true)]);
+    engine.set_which_key_timeout(500);
+
+    let count_timer = started_timer(&engine.key(Char('2'))).expect("which-key timer");
+    let actions = engine.key(Char('g'));
+    let timer = started_timer(&actions).expect("which-key timer");
+    assert_eq!(vec![StartTimer(timer, 500)], actions);
+    // The count is ignored and the timer of the previous keys does nothing.
+    assert_eq!(Vec::<Action>::new(), engine.timeout(count_timer));
+    let continuations = vec![("g".to_string(), "top".to_string()), ("t".to_string(), "open foo".to_string())];
+    assert_eq!(vec![WhichKey(continuations)], engine.timeout(timer));
+    // The continuations are hidden when the keys are forgotten.
+    assert_eq!(vec![WhichKey(vec![])], engine.clear_shortcut());
+    assert_eq!(Vec::<Action>::new(), engine.clear_shortcut());
+}
+
+#[test]
+fn test_engine_keycode_fallback() {
+    let mut engine = engine(&[("q", "first", true)]);
+    assert_eq!(Some(Char('a')), engine.shortcut_key(Some(Char('a')), Some(Char('q'))));
+    engine.add_mapping(NORMAL_MODE, vec![Char('q')], Mapping::new("first".to_string(), true, true,
+        MappingSource::Config));
+    assert_eq!(Some(Char('q')), engine.shortcut_key(Some(Char('a')), Some(Char('q'))));
+    // The key typed is kept when it continues a mapping or when it enters the command mode.
+    engine.add_mapping(NORMAL_MODE, vec![Char('a')], Mapping::new("top".to_string(), false, true,
+        MappingSource::Config));
+    assert_eq!(Some(Char('a')), engine.shortcut_key(Some(Char('a')), Some(Char('q'))));
+    assert_eq!(Some(Char(':')), engine.shortcut_key(Some(Char(':')), Some(Char('q'))));
+    engine.set_keycode_fallback(true);
+    assert_eq!(Some(Char('/')), engine.shortcut_key(Some(Char('/')), Some(Char('q'))));
+    assert_eq!(Some(Char('q')), engine.shortcut_key(Some(Char('x')), Some(Char('q'))));
+}